@@ -0,0 +1,122 @@
+// Compares the ways lilgrep can read a big file:
+//
+//     cargo bench --bench mmap
+//
+// The corpus is generated in a temporary directory, LILGREP_BENCH_MB sets
+// its size (64 MB by default). Each way runs a few times and the best
+// time is kept, so the numbers don't depend on a cold disk cache
+use lilgrep::{Matcher, MmapChoice, Searcher, Sink, SinkLine};
+use std::{
+    env, fs,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const RUNS: usize = 5;
+
+// Only counts, so the benchmark measures the reading and not the printing
+struct Count(usize);
+
+impl Sink for Count {
+    fn matched(&mut self, _line: &SinkLine) -> io::Result<bool> {
+        self.0 += 1;
+        Ok(true)
+    }
+
+    fn wants_spans(&self) -> bool {
+        false
+    }
+}
+
+// Log lines with a rare ERROR among them, about one line in a thousand
+fn generate(path: &Path, megabytes: usize) -> io::Result<()> {
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    let mut written = 0;
+    let mut number: u64 = 0;
+    while written < megabytes * 1024 * 1024 {
+        number += 1;
+        let level = if number.is_multiple_of(997) {
+            "ERROR"
+        } else {
+            "INFO"
+        };
+        let line = format!(
+            "2024-05-{:02} 12:{:02}:{:02} {level} request {number} served in {} ms\n",
+            number % 28 + 1,
+            number % 60,
+            (number / 60) % 60,
+            number % 350,
+        );
+        out.write_all(line.as_bytes())?;
+        written += line.len();
+    }
+    out.flush()
+}
+
+// The best of RUNS runs, and what the last one found
+fn best(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut found = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        found = run();
+        best = best.min(start.elapsed());
+    }
+    (best, found)
+}
+
+fn main() -> io::Result<()> {
+    let megabytes = env::var("LILGREP_BENCH_MB")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(64);
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("corpus.log");
+    generate(&path, megabytes)?;
+    let patterns = ["ERROR".to_string()];
+
+    // What searching a file meant before the Searcher: the whole file
+    // copied into a String, then searched line by line
+    let (copied, expected) = best(|| {
+        let content = fs::read_to_string(&path).unwrap();
+        lilgrep::search(&content, "ERROR").len()
+    });
+
+    let mut results = vec![("read_to_string", copied, expected)];
+    for (name, choice) in [("buffered", MmapChoice::Never), ("mmap", MmapChoice::Auto)] {
+        let matcher = Matcher::new(&patterns, false, false).unwrap();
+        let searcher = Searcher::builder(matcher).mmap(choice).build();
+        let (time, found) = best(|| {
+            let mut count = Count(0);
+            searcher.search_path(&path, &mut count).unwrap();
+            count.0
+        });
+        results.push((name, time, found));
+    }
+
+    // The same search as the mmap one, over a copy of the file: the
+    // difference between the two is what the mapping saves
+    let matcher = Matcher::new(&patterns, false, false).unwrap();
+    let searcher = Searcher::builder(matcher).build();
+    let (time, found) = best(|| {
+        let mut bytes = Vec::new();
+        fs::File::open(&path)
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let mut count = Count(0);
+        searcher.search_slice(&bytes, &mut count).unwrap();
+        count.0
+    });
+    results.push(("read_to_end", time, found));
+
+    println!("corpus: {megabytes} MB, {expected} matching lines, best of {RUNS} runs");
+    for (name, time, found) in results {
+        assert_eq!(expected, found, "{name} found a different number of lines");
+        let throughput = megabytes as f64 / time.as_secs_f64();
+        let speedup = copied.as_secs_f64() / time.as_secs_f64();
+        println!("{name:>15}: {time:>10.2?} {throughput:>8.1} MB/s {speedup:>5.2}x");
+    }
+    Ok(())
+}
@@ -0,0 +1 @@
+fn tasi_generated() {}
@@ -0,0 +1,546 @@
+use std::{error::Error, fmt};
+
+// What comes after an option
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Takes {
+    // Nothing, it's a flag
+    Nothing,
+    // A value, as the next argument or after '=': -A 3, -A3, --after-context=3
+    Value(&'static str),
+    // A value that can only come after '=', the option works without it
+    OptionalValue(&'static str),
+}
+
+// An option lilgrep understands. The help text is generated from these
+pub struct Spec {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub takes: Takes,
+    pub help: &'static str,
+}
+
+const fn flag(short: Option<char>, long: &'static str, help: &'static str) -> Spec {
+    Spec {
+        short,
+        long,
+        takes: Takes::Nothing,
+        help,
+    }
+}
+
+const fn valued(
+    short: Option<char>,
+    long: &'static str,
+    value: &'static str,
+    help: &'static str,
+) -> Spec {
+    Spec {
+        short,
+        long,
+        takes: Takes::Value(value),
+        help,
+    }
+}
+
+pub const SPECS: &[Spec] = &[
+    valued(
+        Some('e'),
+        "regexp",
+        "PATTERN",
+        "Search for PATTERN, can be given many times",
+    ),
+    valued(
+        Some('f'),
+        "file",
+        "FILE",
+        "Search for every pattern in FILE, one per line",
+    ),
+    flag(Some('E'), "regex", "Patterns are regular expressions"),
+    flag(
+        Some('F'),
+        "fixed-strings",
+        "Patterns are plain text, the default",
+    ),
+    valued(
+        None,
+        "query",
+        "QUERY",
+        "Search for a boolean query, like 'a AND NOT \"b c\"'",
+    ),
+    valued(
+        None,
+        "query-scope",
+        "SCOPE",
+        "Where the --query must hold: line, the default, or file",
+    ),
+    valued(
+        None,
+        "fuzzy",
+        "N",
+        "Match text at most N edits away from the patterns",
+    ),
+    flag(Some('w'), "word-regexp", "Match only whole words"),
+    flag(Some('x'), "line-regexp", "Match only whole lines"),
+    flag(Some('U'), "multiline", "Let matches span several lines"),
+    flag(
+        Some('i'),
+        "ignore-case",
+        "Ignore case, also set by the IGNORE_CASE variable",
+    ),
+    flag(
+        Some('s'),
+        "case-sensitive",
+        "Don't ignore case, even with IGNORE_CASE set",
+    ),
+    flag(
+        Some('v'),
+        "invert-match",
+        "Select the lines that don't match",
+    ),
+    valued(
+        Some('m'),
+        "max-count",
+        "NUM",
+        "Stop each file after NUM selected lines",
+    ),
+    flag(
+        Some('n'),
+        "line-number",
+        "Print the line number of each line",
+    ),
+    flag(
+        Some('b'),
+        "byte-offset",
+        "Print the byte offset of each line",
+    ),
+    valued(
+        Some('A'),
+        "after-context",
+        "NUM",
+        "Print NUM lines after each match",
+    ),
+    valued(
+        Some('B'),
+        "before-context",
+        "NUM",
+        "Print NUM lines before each match",
+    ),
+    valued(
+        Some('C'),
+        "context",
+        "NUM",
+        "Print NUM lines before and after each match",
+    ),
+    flag(
+        Some('c'),
+        "count",
+        "Print only how many lines were selected in each file",
+    ),
+    flag(
+        Some('l'),
+        "files-with-matches",
+        "Print only the files with selected lines",
+    ),
+    flag(
+        Some('L'),
+        "files-without-match",
+        "Print only the files without selected lines",
+    ),
+    flag(
+        None,
+        "json",
+        "Print the results as JSON Lines, see the README",
+    ),
+    valued(
+        None,
+        "encoding",
+        "ENC",
+        "Read files as ENC: auto, utf-8, utf-16le, utf-16be or latin1",
+    ),
+    flag(
+        Some('a'),
+        "text",
+        "Search binary files as if they were text",
+    ),
+    flag(
+        Some('z'),
+        "search-zip",
+        "Search inside gzip, bzip2 and zstd compressed files",
+    ),
+    Spec {
+        short: None,
+        long: "color",
+        takes: Takes::OptionalValue("WHEN"),
+        help: "Color the output: auto, always or never (default auto)",
+    },
+    valued(None, "include", "GLOB", "Search only files matching GLOB"),
+    valued(
+        None,
+        "exclude",
+        "GLOB",
+        "Skip files and directories matching GLOB",
+    ),
+    flag(
+        None,
+        "no-ignore",
+        "Don't skip what .gitignore and .ignore files say",
+    ),
+    flag(None, "hidden", "Also search hidden files and directories"),
+    valued(
+        Some('t'),
+        "type",
+        "TYPE",
+        "Search only files of TYPE, like rust or md",
+    ),
+    valued(Some('T'), "type-not", "TYPE", "Skip files of TYPE"),
+    valued(
+        None,
+        "type-add",
+        "NAME:GLOB",
+        "Add GLOB to the files of type NAME",
+    ),
+    flag(None, "type-list", "Print every file type and its globs"),
+    flag(
+        None,
+        "indexed",
+        "Search only the files the index of PATH says can match",
+    ),
+    valued(
+        Some('j'),
+        "threads",
+        "NUM",
+        "Search NUM files at the same time",
+    ),
+    flag(
+        None,
+        "follow",
+        "Keep searching the lines appended to the file, like tail -f",
+    ),
+    valued(
+        None,
+        "replace",
+        "TEMPLATE",
+        "Preview replacing each match by TEMPLATE",
+    ),
+    flag(None, "write", "With --replace, change the files instead"),
+    flag(Some('h'), "help", "Print this help"),
+    flag(Some('V'), "version", "Print the version"),
+];
+
+// An option found in the arguments
+#[derive(Debug, PartialEq)]
+pub struct Given {
+    // The long name, used to know which option it is
+    pub long: &'static str,
+    // How the user wrote it, used in error messages
+    pub written: String,
+    // Empty for flags
+    pub value: String,
+}
+
+// Every argument sorted out: the options, in the order they were given,
+// and everything else
+#[derive(Debug, PartialEq)]
+pub struct Parsed {
+    pub options: Vec<Given>,
+    pub positional: Vec<String>,
+}
+
+// Everything that can go wrong with the arguments. Each variant names the
+// argument at fault
+#[derive(Debug, PartialEq)]
+pub enum ArgError {
+    // --help and --version aren't really errors, but they also stop the
+    // parsing. They carry the text to print
+    Help(String),
+    Version(String),
+    // --type-list stops the parsing the same way
+    TypeList(String),
+    UnknownOption {
+        option: String,
+        suggestion: Option<&'static str>,
+    },
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+        expected: &'static str,
+    },
+    MissingArgument(&'static str),
+    UnexpectedArgument(String),
+    Conflict(&'static str, &'static str),
+    Incompatible(&'static str, &'static str),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Help(text) | ArgError::Version(text) | ArgError::TypeList(text) => {
+                write!(f, "{text}")
+            }
+            ArgError::UnknownOption {
+                option,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "unknown option '{option}', did you mean '--{suggestion}'?"
+            ),
+            ArgError::UnknownOption { option, .. } => write!(f, "unknown option '{option}'"),
+            ArgError::MissingValue(option) => write!(f, "'{option}' needs a value"),
+            ArgError::UnexpectedValue(option) => write!(f, "'{option}' doesn't take a value"),
+            ArgError::InvalidValue {
+                option,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value '{value}' for '{option}', expected {expected}"
+            ),
+            ArgError::MissingArgument(name) => write!(f, "missing the {name} argument"),
+            ArgError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{arg}'"),
+            ArgError::Conflict(option, needed) => {
+                write!(f, "'{option}' only works with '{needed}'")
+            }
+            ArgError::Incompatible(option, with) => {
+                write!(f, "'{option}' can't be used with {with}")
+            }
+        }
+    }
+}
+
+impl Error for ArgError {}
+
+// Sorts the arguments out, without the executable name. Options can come
+// anywhere, and everything after "--" is positional, even if it starts
+// with a dash. A lone "-" is positional too, it means the standard input
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Parsed, ArgError> {
+    let mut options = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            // --name=value or --name
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            // The British spelling is also accepted
+            let name = if name == "colour" { "color" } else { name };
+            let spec = SPECS.iter().find(|spec| spec.long == name).ok_or_else(|| {
+                ArgError::UnknownOption {
+                    option: format!("--{name}"),
+                    suggestion: suggest(name),
+                }
+            })?;
+            let written = format!("--{name}");
+            let value = match (spec.takes, inline) {
+                (Takes::Nothing, Some(_)) => return Err(ArgError::UnexpectedValue(written)),
+                (Takes::Nothing | Takes::OptionalValue(_), None) => String::new(),
+                (Takes::OptionalValue(_), Some(value)) => value,
+                (Takes::Value(_), Some(value)) => value,
+                (Takes::Value(_), None) => args
+                    .next()
+                    .ok_or_else(|| ArgError::MissingValue(written.clone()))?,
+            };
+            options.push(Given {
+                long: spec.long,
+                written,
+                value,
+            });
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // One or more short flags together, -in is the same as -i -n.
+            // When one of them takes a value, the rest of the argument is
+            // the value: -A3
+            for (index, short) in arg[1..].char_indices() {
+                let spec = SPECS
+                    .iter()
+                    .find(|spec| spec.short == Some(short))
+                    .ok_or_else(|| ArgError::UnknownOption {
+                        option: format!("-{short}"),
+                        suggestion: None,
+                    })?;
+                let written = format!("-{short}");
+                let takes_value = matches!(spec.takes, Takes::Value(_));
+                let value = if !takes_value {
+                    String::new()
+                } else {
+                    let rest = &arg[1 + index + short.len_utf8()..];
+                    if rest.is_empty() {
+                        args.next()
+                            .ok_or_else(|| ArgError::MissingValue(written.clone()))?
+                    } else {
+                        rest.to_string()
+                    }
+                };
+                options.push(Given {
+                    long: spec.long,
+                    written,
+                    value,
+                });
+                if takes_value {
+                    break;
+                }
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+    Ok(Parsed {
+        options,
+        positional,
+    })
+}
+
+// The --help text, built from SPECS
+pub fn help() -> String {
+    let mut text = String::from(
+        "Search for QUERY in PATH, printing the lines that match.\n\
+         \n\
+         Usage: lilgrep [OPTIONS] PATH QUERY\n\
+         \x20      lilgrep [OPTIONS] PATH -e PATTERN...\n\
+         \x20      lilgrep [OPTIONS] PATH --query QUERY\n\
+         \x20      lilgrep index build DIR\n\
+         \n\
+         PATH can be a file, a directory, searched recursively, or - for the\n\
+         standard input. Options can come anywhere, use -- to end them.\n\
+         index build writes the trigram index of DIR used by --indexed.\n\
+         \n\
+         Options:\n",
+    );
+    let columns: Vec<String> = SPECS
+        .iter()
+        .map(|spec| {
+            let short = match spec.short {
+                Some(short) => format!("-{short}, "),
+                None => "    ".to_string(),
+            };
+            let value = match spec.takes {
+                Takes::Nothing => String::new(),
+                Takes::Value(name) => format!(" <{name}>"),
+                Takes::OptionalValue(name) => format!("[=<{name}>]"),
+            };
+            format!("{short}--{}{value}", spec.long)
+        })
+        .collect();
+    let width = columns.iter().map(String::len).max().unwrap_or(0);
+    for (column, spec) in columns.iter().zip(SPECS) {
+        text.push_str(&format!("  {column:width$}  {}\n", spec.help));
+    }
+    text.push_str(
+        "\nExit status: 0 if something was found, 1 if nothing was, 2 for invalid arguments,\n\
+         3 for an invalid pattern, 4 for an invalid glob, 5 for a missing file,\n\
+         6 for a permission error, 7 for invalid UTF-8, 8 for other I/O errors,\n\
+         9 when the output can't be written and 10 for a missing or unusable index.\n",
+    );
+    text
+}
+
+pub fn version() -> String {
+    format!("lilgrep {}\n", env!("CARGO_PKG_VERSION"))
+}
+
+// The long option closest to a mistyped one, if it's close enough
+fn suggest(name: &str) -> Option<&'static str> {
+    SPECS
+        .iter()
+        .map(|spec| (distance(name, spec.long), spec.long))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, long)| long)
+}
+
+// Levenshtein distance: how many chars must be inserted, removed or
+// replaced to turn a into b
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Only the previous row of the table is needed
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &[&str]) -> Result<Parsed, ArgError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn longs(parsed: &Parsed) -> Vec<(&str, &str)> {
+        parsed
+            .options
+            .iter()
+            .map(|given| (given.long, given.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn options_anywhere() {
+        let parsed =
+            parse_str(&["-in", "file.txt", "--context=2", "tasi", "-A3", "-m", "1"]).unwrap();
+        assert_eq!(
+            vec![
+                ("ignore-case", ""),
+                ("line-number", ""),
+                ("context", "2"),
+                ("after-context", "3"),
+                ("max-count", "1"),
+            ],
+            longs(&parsed)
+        );
+        assert_eq!(vec!["file.txt", "tasi"], parsed.positional);
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let parsed = parse_str(&["-n", "--", "file.txt", "-v", "--json"]).unwrap();
+        assert_eq!(vec![("line-number", "")], longs(&parsed));
+        assert_eq!(vec!["file.txt", "-v", "--json"], parsed.positional);
+        let parsed = parse_str(&["-", "tasi"]).unwrap();
+        assert_eq!(vec!["-", "tasi"], parsed.positional);
+    }
+
+    #[test]
+    fn errors_name_the_argument() {
+        assert_eq!(
+            "unknown option '--hiden', did you mean '--hidden'?",
+            parse_str(&["--hiden"]).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "unknown option '-X'",
+            parse_str(&["-nX"]).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "'--include' needs a value",
+            parse_str(&["file.txt", "--include"])
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "'--json' doesn't take a value",
+            parse_str(&["--json=yes"]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn generated_help() {
+        let help = help();
+        assert!(help.contains("Usage: lilgrep [OPTIONS] PATH QUERY"));
+        // Every option is there
+        for spec in SPECS {
+            assert!(help.contains(&format!("--{}", spec.long)));
+        }
+        assert!(help.contains("  -A, --after-context <NUM>"));
+    }
+}
@@ -0,0 +1,99 @@
+use bzip2::read::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use std::io::{self, BufRead, BufReader};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+// The magic bytes each format starts with. bzip2 has only three, so the
+// block size digit that always follows them is checked too, or a text
+// starting with "BZh" would look compressed
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const BZIP2_BLOCK_SIZES: std::ops::RangeInclusive<u8> = b'1'..=b'9';
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+// The formats -z knows how to read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+impl Format {
+    // Looks at the first bytes of the input. The file name doesn't
+    // matter, a rotated log can be called app.log.1 and still be gzip
+    pub fn sniff(head: &[u8]) -> Option<Format> {
+        if head.starts_with(GZIP_MAGIC) {
+            Some(Format::Gzip)
+        } else if head.starts_with(BZIP2_MAGIC)
+            && head
+                .get(BZIP2_MAGIC.len())
+                .is_some_and(|size| BZIP2_BLOCK_SIZES.contains(size))
+        {
+            Some(Format::Bzip2)
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Some(Format::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+// Gives back a reader of the decompressed content if reader is
+// compressed, or reader itself otherwise. The content is decompressed
+// while it's read, a big archive is never in memory at once.
+// Files made of several compressed streams one after the other, like
+// the ones made by cat a.gz b.gz, are read to the end
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    Ok(match Format::sniff(reader.fill_buf()?) {
+        Some(Format::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Some(Format::Bzip2) => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        Some(Format::Zstd) => Box::new(BufReader::new(ZstdDecoder::with_buffer(reader)?)),
+        None => Box::new(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Read};
+
+    fn decompressed(path: &str) -> String {
+        let file = BufReader::new(File::open(path).unwrap());
+        let mut text = String::new();
+        decompress(file).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn every_format() {
+        let plain = decompressed("resources/compressed/app.log");
+        assert!(plain.starts_with("INFO starting\n"));
+        for file in ["app.log.1.gz", "app.log.2.bz2", "app.log.3.zst"] {
+            assert_eq!(plain, decompressed(&format!("resources/compressed/{file}")));
+        }
+    }
+
+    #[test]
+    fn plain_text_like_a_magic() {
+        assert_eq!(None, Format::sniff(b"BZh is my name"));
+        assert_eq!(None, Format::sniff(b"BZh"));
+        assert_eq!(Some(Format::Bzip2), Format::sniff(b"BZh91AY&SY"));
+        let input: &[u8] = b"BZh is my name\n";
+        let mut text = String::new();
+        decompress(input)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!("BZh is my name\n", text);
+    }
+
+    #[test]
+    fn corrupted() {
+        // The gzip magic followed by garbage
+        let input: &[u8] = &[0x1F, 0x8B, 0x08, 0x00, 0xFF, 0xFF];
+        let mut text = String::new();
+        let result = decompress(input).unwrap().read_to_string(&mut text);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+// How many bytes are decoded at once
+const CHUNK_LEN: usize = 8192;
+
+// The text encoding of the input, chosen with --encoding. Whatever it is,
+// the search always sees UTF-8, so byte offsets (-b) count the bytes of
+// the decoded text and not the bytes of the file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    // UTF-8, unless a byte order mark (BOM) says it's UTF-16
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // ISO-8859-1, every byte is the char with the same number
+    Latin1,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name.to_lowercase().as_str() {
+            "auto" => Some(Encoding::Auto),
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    // Looks for a BOM at the start of the input. The BOM itself isn't
+    // text, it's never part of the first line
+    pub(crate) fn sniff(head: &[u8]) -> Option<(Encoding, usize)> {
+        if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            Some((Encoding::Utf8, 3))
+        } else if head.starts_with(&[0xFF, 0xFE]) {
+            Some((Encoding::Utf16Le, 2))
+        } else if head.starts_with(&[0xFE, 0xFF]) {
+            Some((Encoding::Utf16Be, 2))
+        } else {
+            None
+        }
+    }
+}
+
+// A reader giving back UTF-8, whatever the encoding of the input was.
+// UTF-8 input is read as it is, without copying it around
+pub enum Decoded<R> {
+    Plain(R),
+    Transcoded(BufReader<Transcoder<R>>),
+}
+
+// Wraps reader so it gives back UTF-8. A BOM wins over Auto, and is
+// dropped when it agrees with the given encoding
+pub fn decode<R: BufRead>(mut reader: R, encoding: Encoding) -> io::Result<Decoded<R>> {
+    let sniffed = Encoding::sniff(reader.fill_buf()?);
+    let encoding = match sniffed {
+        Some((found, len)) if encoding == Encoding::Auto || encoding == found => {
+            reader.consume(len);
+            found
+        }
+        _ => encoding,
+    };
+    Ok(match encoding {
+        Encoding::Auto | Encoding::Utf8 => Decoded::Plain(reader),
+        other => Decoded::Transcoded(BufReader::new(Transcoder::new(reader, other))),
+    })
+}
+
+impl<R: BufRead> Read for Decoded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoded::Plain(reader) => reader.read(buf),
+            Decoded::Transcoded(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Decoded<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Decoded::Plain(reader) => reader.fill_buf(),
+            Decoded::Transcoded(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Decoded::Plain(reader) => reader.consume(amt),
+            Decoded::Transcoded(reader) => reader.consume(amt),
+        }
+    }
+}
+
+// Turns UTF-16 or Latin-1 into UTF-8 a chunk at a time, so big files
+// never have to be in memory at once
+pub struct Transcoder<R> {
+    inner: R,
+    encoding: Encoding,
+    // Bytes read but not decoded yet: half of a UTF-16 code unit, or a
+    // high surrogate waiting for the low one
+    pending: Vec<u8>,
+    // Decoded UTF-8 not given back yet
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R: BufRead> Transcoder<R> {
+    fn new(inner: R, encoding: Encoding) -> Transcoder<R> {
+        Transcoder {
+            inner,
+            encoding,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            position: 0,
+        }
+    }
+
+    // Decodes the next chunk into self.decoded. Returns false at the end
+    // of the input
+    fn refill(&mut self) -> io::Result<bool> {
+        self.decoded.clear();
+        self.position = 0;
+        while self.decoded.is_empty() {
+            let chunk = self.inner.fill_buf()?;
+            let end = chunk.is_empty();
+            let len = chunk.len().min(CHUNK_LEN);
+            self.pending.extend_from_slice(&chunk[..len]);
+            self.inner.consume(len);
+            if end && self.pending.is_empty() {
+                return Ok(false);
+            }
+
+            let text = match self.encoding {
+                Encoding::Utf16Le | Encoding::Utf16Be => self.decode_utf16(end),
+                _ => self.pending.drain(..).map(char::from).collect(),
+            };
+            self.decoded.extend_from_slice(text.as_bytes());
+        }
+        Ok(true)
+    }
+
+    // Decodes every complete code unit of self.pending. Broken surrogate
+    // pairs become U+FFFD, like invalid UTF-8 does
+    fn decode_utf16(&mut self, end: bool) -> String {
+        let little = self.encoding == Encoding::Utf16Le;
+        let mut units: Vec<u16> = self
+            .pending
+            .chunks_exact(2)
+            .map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if little {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            })
+            .collect();
+        let mut keep = self.pending.len() % 2;
+        // A high surrogate at the end may be completed by the next chunk
+        if !end
+            && units
+                .last()
+                .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+        {
+            units.pop();
+            keep += 2;
+        }
+        self.pending.drain(..self.pending.len() - keep);
+        if end {
+            // An odd byte at the very end can't be decoded at all
+            self.pending.clear();
+        }
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+}
+
+impl<R: BufRead> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.decoded.len() && !self.refill()? {
+            return Ok(0);
+        }
+        let rest = &self.decoded[self.position..];
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(input: &[u8], encoding: Encoding) -> String {
+        let mut text = String::new();
+        decode(input, encoding)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!(
+            "héllo\n",
+            decoded(b"\xEF\xBB\xBFh\xC3\xA9llo\n", Encoding::Auto)
+        );
+        let mut le = vec![0xFF, 0xFE];
+        let mut be = vec![0xFE, 0xFF];
+        for unit in "tasi 🦀\n".encode_utf16() {
+            le.extend_from_slice(&unit.to_le_bytes());
+            be.extend_from_slice(&unit.to_be_bytes());
+        }
+        assert_eq!("tasi 🦀\n", decoded(&le, Encoding::Auto));
+        assert_eq!("tasi 🦀\n", decoded(&be, Encoding::Auto));
+        // Without the BOM the encoding has to be given
+        assert_eq!("tasi 🦀\n", decoded(&le[2..], Encoding::Utf16Le));
+    }
+
+    #[test]
+    fn surrogates_across_chunks() {
+        // The crab is a surrogate pair, split between two reads
+        let mut input = Vec::new();
+        for unit in "a"
+            .repeat(CHUNK_LEN / 2 - 1)
+            .encode_utf16()
+            .chain("🦀".encode_utf16())
+        {
+            input.extend_from_slice(&unit.to_le_bytes());
+        }
+        let text = decoded(&input, Encoding::Utf16Le);
+        assert!(text.ends_with("a🦀"));
+        // A lonely high surrogate at the end
+        assert_eq!(
+            "a\u{FFFD}",
+            decoded(&[0x61, 0x00, 0x3D, 0xD8], Encoding::Utf16Le)
+        );
+    }
+
+    #[test]
+    fn latin1() {
+        assert_eq!("café señor", decoded(b"caf\xE9 se\xF1or", Encoding::Latin1));
+        assert_eq!(Some(Encoding::Latin1), Encoding::parse("ISO-8859-1"));
+        assert_eq!(None, Encoding::parse("ebcdic"));
+    }
+}
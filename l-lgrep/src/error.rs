@@ -0,0 +1,190 @@
+use crate::ArgError;
+use std::{error::Error, fmt, io};
+
+// Everything that can make lilgrep fail. Library users can match on the
+// variant to know what went wrong, and main turns each one into its own
+// exit status
+#[derive(Debug)]
+pub enum LilgrepError {
+    // The command line was wrong, or --help and --version were asked
+    Args(ArgError),
+    // A pattern couldn't be compiled, like an unclosed group in -E mode
+    Pattern {
+        pattern: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    // An --include or --exclude glob is invalid
+    Glob {
+        glob: String,
+        source: ignore::Error,
+    },
+    NotFound {
+        path: String,
+        source: io::Error,
+    },
+    PermissionDenied {
+        path: String,
+        source: io::Error,
+    },
+    // A file that had to be text, like a -f patterns file, isn't UTF-8
+    InvalidUtf8 {
+        path: String,
+        source: io::Error,
+    },
+    // Any other problem reading or writing a file
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    // Writing the results to the standard output failed
+    Output(io::Error),
+    // The index of --indexed is missing or can't be used
+    Index {
+        path: String,
+        problem: String,
+    },
+    // Some files of a directory couldn't be searched. Each one was
+    // already reported when it failed, first is the error of the first
+    Skipped {
+        files: usize,
+        first: Box<LilgrepError>,
+    },
+}
+
+impl LilgrepError {
+    // Sorts an I/O error that happened while working on path
+    pub fn io(path: &str, source: io::Error) -> LilgrepError {
+        let path = path.to_string();
+        match source.kind() {
+            io::ErrorKind::NotFound => LilgrepError::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => LilgrepError::PermissionDenied { path, source },
+            io::ErrorKind::InvalidData => LilgrepError::InvalidUtf8 { path, source },
+            // Reading a file never breaks a pipe, only writing the
+            // results to a closed output does
+            io::ErrorKind::BrokenPipe => LilgrepError::Output(source),
+            _ => LilgrepError::Io { path, source },
+        }
+    }
+
+    // 0 and 1 mean found and not found, like in grep, so errors start at 2
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LilgrepError::Args(
+                ArgError::Help(_) | ArgError::Version(_) | ArgError::TypeList(_),
+            ) => 0,
+            LilgrepError::Args(_) => 2,
+            LilgrepError::Pattern { .. } => 3,
+            LilgrepError::Glob { .. } => 4,
+            LilgrepError::NotFound { .. } => 5,
+            LilgrepError::PermissionDenied { .. } => 6,
+            LilgrepError::InvalidUtf8 { .. } => 7,
+            LilgrepError::Io { .. } => 8,
+            LilgrepError::Output(_) => 9,
+            LilgrepError::Index { .. } => 10,
+            // Like grep, a failure wins over what was found in the other
+            // files
+            LilgrepError::Skipped { first, .. } => first.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for LilgrepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LilgrepError::Args(err) => write!(f, "{err}"),
+            LilgrepError::Pattern { pattern, source } => {
+                write!(f, "invalid pattern '{pattern}': {source}")
+            }
+            LilgrepError::Glob { glob, source } => write!(f, "invalid glob '{glob}': {source}"),
+            LilgrepError::NotFound { path, .. } => write!(f, "{path}: no such file or directory"),
+            LilgrepError::PermissionDenied { path, .. } => write!(f, "{path}: permission denied"),
+            LilgrepError::InvalidUtf8 { path, .. } => write!(f, "{path}: not valid UTF-8"),
+            LilgrepError::Io { path, source } => write!(f, "{path}: {source}"),
+            LilgrepError::Output(source) => write!(f, "writing the output: {source}"),
+            LilgrepError::Index { path, problem } => write!(f, "{path}: {problem}"),
+            LilgrepError::Skipped { files: 1, .. } => write!(f, "1 file couldn't be searched"),
+            LilgrepError::Skipped { files, .. } => write!(f, "{files} files couldn't be searched"),
+        }
+    }
+}
+
+impl Error for LilgrepError {
+    // The error that caused this one, so callers can walk down the chain
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LilgrepError::Args(err) => Some(err),
+            LilgrepError::Pattern { source, .. } => Some(source.as_ref()),
+            LilgrepError::Glob { source, .. } => Some(source),
+            LilgrepError::NotFound { source, .. }
+            | LilgrepError::PermissionDenied { source, .. }
+            | LilgrepError::InvalidUtf8 { source, .. }
+            | LilgrepError::Io { source, .. }
+            | LilgrepError::Output(source) => Some(source),
+            LilgrepError::Index { .. } => None,
+            LilgrepError::Skipped { first, .. } => Some(first.as_ref()),
+        }
+    }
+}
+
+// Lets the ? operator turn argument errors into LilgrepError
+impl From<ArgError> for LilgrepError {
+    fn from(err: ArgError) -> LilgrepError {
+        LilgrepError::Args(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_by_kind() {
+        let err = LilgrepError::io("a.txt", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(err, LilgrepError::PermissionDenied { .. }));
+        assert_eq!("a.txt: permission denied", err.to_string());
+        assert!(err.source().is_some());
+
+        let err = LilgrepError::io("a.txt", io::Error::from(io::ErrorKind::InvalidData));
+        assert!(matches!(err, LilgrepError::InvalidUtf8 { .. }));
+        let err = LilgrepError::io("a.txt", io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(matches!(err, LilgrepError::Output(_)));
+    }
+
+    #[test]
+    fn distinct_exit_codes() {
+        let errors = [
+            LilgrepError::Args(ArgError::MissingArgument("PATH")),
+            LilgrepError::Pattern {
+                pattern: "(".to_string(),
+                source: "unclosed group".into(),
+            },
+            LilgrepError::Glob {
+                glob: "[".to_string(),
+                source: ignore::Error::Glob {
+                    glob: None,
+                    err: "unclosed".to_string(),
+                },
+            },
+            LilgrepError::io("a", io::Error::from(io::ErrorKind::NotFound)),
+            LilgrepError::io("a", io::Error::from(io::ErrorKind::PermissionDenied)),
+            LilgrepError::io("a", io::Error::from(io::ErrorKind::InvalidData)),
+            LilgrepError::io("a", io::Error::from(io::ErrorKind::Other)),
+            LilgrepError::Output(io::Error::from(io::ErrorKind::BrokenPipe)),
+            LilgrepError::Index {
+                path: ".lilgrep-index".to_string(),
+                problem: "not a lilgrep index".to_string(),
+            },
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(LilgrepError::exit_code).collect();
+        codes.dedup();
+        assert_eq!(errors.len(), codes.len());
+        assert!(codes.iter().all(|code| *code >= 2));
+        let help = LilgrepError::Args(ArgError::Help(String::new()));
+        assert_eq!(0, help.exit_code());
+        let skipped = LilgrepError::Skipped {
+            files: 2,
+            first: Box::new(LilgrepError::io("a", io::Error::from(io::ErrorKind::Other))),
+        };
+        assert_eq!(8, skipped.exit_code());
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+// How long --follow waits before looking for new lines again
+pub const POLL: Duration = Duration::from_millis(250);
+
+// A reader of a file that never ends, like tail -f. At the end of the
+// file it waits for more lines instead of giving back 0. When the file
+// is truncated, or replaced by a new one as log rotation does, it starts
+// again from the beginning of the file now at path
+#[derive(Debug)]
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    identity: (u64, u64),
+    // How much of the current file was read
+    position: u64,
+    poll: Duration,
+}
+
+impl Follow {
+    pub fn open(path: &Path, poll: Duration) -> io::Result<Follow> {
+        let file = File::open(path)?;
+        let identity = identity(&file.metadata()?);
+        Ok(Follow {
+            path: path.to_path_buf(),
+            file,
+            identity,
+            position: 0,
+            poll,
+        })
+    }
+
+    // Opens the file again when path isn't the file being read anymore,
+    // or when it got shorter than what was read. Returns if it did
+    fn reopen(&mut self) -> io::Result<bool> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // Rotated away and the new file isn't there yet, the old
+            // one is still followed meanwhile
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let what = if identity(&metadata) != self.identity {
+            "replaced"
+        } else if metadata.len() < self.position {
+            "truncated"
+        } else {
+            return Ok(false);
+        };
+        // Like tail, the output only gets the lines, the news go to the
+        // standard error
+        eprintln!("lilgrep: {}: file {what}", self.path.display());
+        self.file = File::open(&self.path)?;
+        self.identity = identity(&self.file.metadata()?);
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            // Everything written to the old file was read by now, it's
+            // safe to move to the new one
+            if !self.reopen()? {
+                thread::sleep(self.poll);
+            }
+        }
+    }
+}
+
+// The device and inode of a file, they change when another file takes
+// its name
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+// Without inodes only truncation is noticed, a rotated file usually
+// starts shorter than the old one anyway
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Matcher, Searcher, Sink, SinkLine};
+    use std::{
+        fs::OpenOptions,
+        io::{BufReader, Write},
+        sync::mpsc::{self, Sender},
+    };
+
+    // Tells the writer below about every match
+    struct Tell(Sender<String>);
+
+    impl Sink for Tell {
+        fn matched(&mut self, line: &SinkLine) -> io::Result<bool> {
+            self.0.send(line.line.to_string()).unwrap();
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn appends_rotation_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "ERROR one\nINFO two\n").unwrap();
+
+        // Each change waits for the match before it, so the test doesn't
+        // depend on timing
+        let (sender, receiver) = mpsc::channel();
+        let log = path.clone();
+        let writer = thread::spawn(move || {
+            let mut seen = vec![receiver.recv().unwrap()];
+            let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+            file.write_all(b"INFO three\nERROR four\n").unwrap();
+
+            seen.push(receiver.recv().unwrap());
+            fs::rename(&log, log.with_extension("log.1")).unwrap();
+            fs::write(&log, "ERROR five, after the rotation\n").unwrap();
+
+            seen.push(receiver.recv().unwrap());
+            fs::write(&log, "ERROR six\n").unwrap();
+            seen.push(receiver.recv().unwrap());
+            seen
+        });
+
+        let matcher = Matcher::new(&["ERROR".to_string()], false, false).unwrap();
+        // The search only ends because of -m
+        let searcher = Searcher::builder(matcher).max_count(Some(4)).build();
+        let follow = Follow::open(&path, Duration::from_millis(5)).unwrap();
+        let count = searcher
+            .search_reader(BufReader::new(follow), &mut Tell(sender))
+            .unwrap();
+        assert_eq!(4, count);
+        let seen = writer.join().unwrap();
+        assert_eq!(
+            vec![
+                "ERROR one",
+                "ERROR four",
+                "ERROR five, after the rotation",
+                "ERROR six"
+            ],
+            seen
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+// Patterns up to this many chars fit in the bit vectors
+pub const MAX_LEN: usize = 64;
+
+// Approximate search for --fuzzy: finds the parts of a line that are at
+// most max edits (inserted, removed or replaced chars) away from the
+// pattern.
+//
+// It's Myers' bit-parallel algorithm (G. Myers, "A fast bit-vector
+// algorithm for approximate string matching based on dynamic
+// programming", 1999). The column of the usual edit distance table is
+// kept as two bit vectors of the pattern length, the places where it goes
+// up and where it goes down, and a whole column is worked out with a few
+// word operations for each char of the line
+#[derive(Debug)]
+pub struct Fuzzy {
+    pattern: Vec<char>,
+    max: usize,
+    ignore_case: bool,
+    // For each char of the pattern, a bit set at every place it's in
+    peq: HashMap<char, u64>,
+}
+
+impl Fuzzy {
+    // Fails for an empty pattern, one longer than MAX_LEN chars, or when
+    // max edits would be enough to match anything
+    pub fn new(pattern: &str, max: usize, ignore_case: bool) -> Result<Fuzzy, String> {
+        let pattern: Vec<char> = pattern.chars().map(|c| fold(c, ignore_case)).collect();
+        if pattern.len() > MAX_LEN {
+            return Err(format!("fuzzy patterns can have at most {MAX_LEN} chars"));
+        }
+        if max >= pattern.len() {
+            return Err(format!(
+                "a distance of {max} matches anything, it must be below the {} chars of the pattern",
+                pattern.len()
+            ));
+        }
+        let mut peq = HashMap::new();
+        for (index, c) in pattern.iter().enumerate() {
+            *peq.entry(*c).or_insert(0) |= 1 << index;
+        }
+        Ok(Fuzzy {
+            pattern,
+            max,
+            ignore_case,
+            peq,
+        })
+    }
+
+    // Every match of the line, in order and without overlapping, with its
+    // distance. Every place where a match can end gives a candidate, the
+    // longest one ending there. Where candidates overlap the closest one
+    // wins, and the longest one when they're as close
+    pub fn find_all(&self, line: &str) -> Vec<(Range<usize>, usize)> {
+        let chars: Vec<(usize, char)> = line
+            .char_indices()
+            .map(|(at, c)| (at, fold(c, self.ignore_case)))
+            .collect();
+        let text: Vec<char> = chars.iter().map(|(_, c)| *c).collect();
+        let byte = |index: usize| chars.get(index).map_or(line.len(), |(at, _)| *at);
+
+        let mut candidates: Vec<(Range<usize>, usize)> = self
+            .ends(&text)
+            .into_iter()
+            .map(|(end, distance)| (self.start(&text[..end], distance)..end, distance))
+            .collect();
+        candidates.sort_by_key(|(span, distance)| (*distance, Reverse(span.len()), span.start));
+
+        // The candidates taken so far, by start. They never overlap, so
+        // only the last one starting before the end of a candidate can
+        // overlap it
+        let mut taken: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+        for (span, distance) in candidates {
+            let overlaps = taken
+                .range(..span.end)
+                .next_back()
+                .is_some_and(|(_, (end, _))| *end > span.start);
+            if !overlaps {
+                taken.insert(span.start, (span.end, distance));
+            }
+        }
+        taken
+            .into_iter()
+            .map(|(start, (end, distance))| (byte(start)..byte(end), distance))
+            .collect()
+    }
+
+    // The scan itself: every place, counted in chars, where a match can
+    // end, with the smallest distance of the matches ending there
+    fn ends(&self, text: &[char]) -> Vec<(usize, usize)> {
+        let len = self.pattern.len();
+        let last = 1 << (len - 1);
+        let mask = if len == MAX_LEN {
+            u64::MAX
+        } else {
+            (1 << len) - 1
+        };
+        // The first column of the table goes up by one on every row
+        let mut up = mask;
+        let mut down = 0;
+        let mut distance = len;
+        let mut ends = Vec::new();
+        for (index, c) in text.iter().enumerate() {
+            let eq = self.peq.get(c).copied().unwrap_or(0);
+            let xv = eq | down;
+            let xh = (((eq & up).wrapping_add(up)) ^ up) | eq;
+            let mut h_up = down | !(xh | up);
+            let mut h_down = up & xh;
+            if h_up & last != 0 {
+                distance += 1;
+            } else if h_down & last != 0 {
+                distance -= 1;
+            }
+            // A match can start anywhere, the first row stays at zero
+            h_up <<= 1;
+            h_down <<= 1;
+            up = (h_down | !(xv | h_up)) & mask;
+            down = h_up & xv & mask;
+            if distance <= self.max {
+                ends.push((index + 1, distance));
+            }
+        }
+        ends
+    }
+
+    // Where the longest match ending at the end of text, with this
+    // distance, starts. Only runs for the matches, so the plain table
+    // is good enough: the pattern and the text are compared backwards
+    fn start(&self, text: &[char], distance: usize) -> usize {
+        let len = self.pattern.len();
+        let mut start = text.len().saturating_sub(len);
+        let mut previous: Vec<usize> = (0..=len).collect();
+        for taken in 1..=text.len().min(len + self.max) {
+            let c = text[text.len() - taken];
+            let mut current = vec![taken; len + 1];
+            for row in 1..=len {
+                let replace = previous[row - 1] + usize::from(self.pattern[len - row] != c);
+                current[row] = replace.min(previous[row] + 1).min(current[row - 1] + 1);
+            }
+            if current[len] == distance {
+                start = text.len() - taken;
+            }
+            previous = current;
+        }
+        start
+    }
+}
+
+// With -i every char is compared by its lowercase
+fn fold(c: char, ignore_case: bool) -> char {
+    if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(pattern: &str, max: usize, line: &str) -> Vec<(String, usize)> {
+        let fuzzy = Fuzzy::new(pattern, max, false).unwrap();
+        fuzzy
+            .find_all(line)
+            .into_iter()
+            .map(|(span, distance)| (line[span].to_string(), distance))
+            .collect()
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(vec![("tasi".to_string(), 0)], found("tasi", 1, "a tasi b"));
+        // One replaced, one removed and one inserted char
+        let line = "tazi, tsi and taasi";
+        let expected = vec![
+            ("tazi".to_string(), 1),
+            ("tsi".to_string(), 1),
+            ("taasi".to_string(), 1),
+        ];
+        assert_eq!(expected, found("tasi", 1, line));
+        assert!(found("tasi", 1, "tzzi").is_empty());
+        assert_eq!(vec![("tzzi".to_string(), 2)], found("tasi", 2, "tzzi"));
+        // A swap is two edits. The whole word is as close as its start
+        // "recie", and longer
+        assert_eq!(
+            vec![("recieve".to_string(), 2)],
+            found("receive", 2, "I recieve it")
+        );
+        assert!(found("receive", 1, "recieve").is_empty());
+    }
+
+    #[test]
+    fn unicode_and_case() {
+        let fuzzy = Fuzzy::new("CAFÉ", 1, true).unwrap();
+        let line = "le cafe é bom";
+        let found = fuzzy.find_all(line);
+        assert_eq!(vec![(3..7, 1)], found);
+    }
+
+    #[test]
+    fn same_as_the_table() {
+        // The bit vectors give the same distances as the plain table, for
+        // every end of every line
+        let lines = [
+            "kitten sitting",
+            "saturday sunday",
+            "aaaaaa",
+            "abcabcabd",
+            "",
+        ];
+        for pattern in ["sitting", "sunday", "aab", "abd", "x"] {
+            let fuzzy = Fuzzy::new(pattern, pattern.len() - 1, false).unwrap();
+            for line in lines {
+                let text: Vec<char> = line.chars().collect();
+                let expected: Vec<(usize, usize)> = (1..=text.len())
+                    .map(|end| (end, best_ending_at(&fuzzy.pattern, &text[..end])))
+                    .filter(|(_, distance)| *distance <= fuzzy.max)
+                    .collect();
+                assert_eq!(expected, fuzzy.ends(&text), "{pattern} in {line}");
+            }
+        }
+    }
+
+    // The smallest distance between the pattern and any text ending at
+    // the end of text, the slow way
+    fn best_ending_at(pattern: &[char], text: &[char]) -> usize {
+        (0..=text.len())
+            .map(|start| distance(pattern, &text[start..]))
+            .min()
+            .unwrap()
+    }
+
+    fn distance(a: &[char], b: &[char]) -> usize {
+        let mut previous: Vec<usize> = (0..=b.len()).collect();
+        for (i, x) in a.iter().enumerate() {
+            let mut current = vec![i + 1; b.len() + 1];
+            for (j, y) in b.iter().enumerate() {
+                current[j + 1] = (previous[j] + usize::from(x != y))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1);
+            }
+            previous = current;
+        }
+        previous[b.len()]
+    }
+
+    #[test]
+    fn limits() {
+        assert!(Fuzzy::new("ab", 2, false).is_err());
+        assert!(Fuzzy::new(&"a".repeat(MAX_LEN + 1), 1, false).is_err());
+        let long = "ab".repeat(MAX_LEN / 2);
+        let fuzzy = Fuzzy::new(&long, 2, false).unwrap();
+        let line = format!("x{}x", long.replacen("ab", "b", 1));
+        assert_eq!(1, fuzzy.find_all(&line)[0].1);
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{
+    compress::Format, matcher::fold_case, walk, Encoding, LilgrepError, MatcherOptions, WalkOptions,
+};
+use regex_syntax::{hir::literal::Extractor, ParserBuilder};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, Metadata},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str,
+    time::UNIX_EPOCH,
+};
+use tempfile::NamedTempFile;
+
+// The index of a directory is this file, right inside the directory.
+// It's hidden, so it's never searched or indexed by default
+pub const INDEX_FILE: &str = ".lilgrep-index";
+
+// The index file, every number little endian:
+//
+//     magic     4 bytes, "LGIX"
+//     version   u32, VERSION
+//     files     u32, how many entries follow
+//     and for each file, sorted by path:
+//         path      u32 length and the UTF-8 bytes of the path, relative
+//                   to the indexed directory and with / between names
+//         size      u64, in bytes
+//         modified  u64 seconds and u32 nanoseconds since the Unix epoch
+//         flags     u8, bit 0 set when the file is always searched
+//         trigrams  u32 count and the trigrams, each one an u32, sorted
+//
+// A trigram is three bytes in a row of the case folded text of the file,
+// packed as b0 << 16 | b1 << 8 | b2. Any change of this layout must bump
+// VERSION, an index of another version is refused and built again
+const MAGIC: &[u8; 4] = b"LGIX";
+const VERSION: u32 = 1;
+
+// What the index knows about a file
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    size: u64,
+    modified: (u64, u32),
+    // Compressed and UTF-16 files don't have their text as it is on disk,
+    // their trigrams say nothing, so they're always searched
+    always: bool,
+    trigrams: Vec<u32>,
+}
+
+impl Entry {
+    fn new(metadata: &Metadata, content: &[u8]) -> Entry {
+        let transcoded = !matches!(Encoding::sniff(content), None | Some((Encoding::Utf8, _)));
+        Entry {
+            size: metadata.len(),
+            modified: modified(metadata),
+            always: transcoded || Format::sniff(content).is_some(),
+            trigrams: trigrams(&String::from_utf8_lossy(content)),
+        }
+    }
+
+    // The file didn't change since it was indexed. Like make, it trusts
+    // the size and the modification time instead of reading the file
+    fn is_fresh(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.len() && self.modified == modified(metadata)
+    }
+}
+
+// The trigram index of a directory, see INDEX_FILE for its format
+#[derive(Debug, Default, PartialEq)]
+pub struct Index {
+    entries: BTreeMap<String, Entry>,
+}
+
+// What index build did
+#[derive(Debug, Default, PartialEq)]
+pub struct BuildStats {
+    // Every file in the index
+    pub files: usize,
+    // The ones read again, because they're new or changed
+    pub updated: usize,
+    // The ones that are gone since the last build
+    pub removed: usize,
+}
+
+impl Index {
+    // Reads the index of the directory root
+    pub fn load(root: &str) -> Result<Index, LilgrepError> {
+        let path = Path::new(root).join(INDEX_FILE);
+        let label = path.display().to_string();
+        let problem = |problem: String| LilgrepError::Index {
+            path: label.clone(),
+            problem,
+        };
+        let bytes = fs::read(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => problem(format!(
+                "no index, build it with 'lilgrep index build {root}'"
+            )),
+            _ => LilgrepError::io(&label, err),
+        })?;
+        Index::read(&bytes).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => problem(err.to_string()),
+            io::ErrorKind::UnexpectedEof => problem("corrupted index, build it again".to_string()),
+            _ => LilgrepError::io(&label, err),
+        })
+    }
+
+    // The whole file is in memory, so every length can be checked against
+    // the bytes left before anything is allocated for it
+    fn read(mut reader: &[u8]) -> io::Result<Index> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(corrupted("not a lilgrep index"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(corrupted(&format!(
+                "index of version {version}, this lilgrep reads version {VERSION}, build it again"
+            )));
+        }
+        let mut entries = BTreeMap::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let mut path = vec![0; read_len(&mut reader, 1)?];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| corrupted("corrupted index path"))?;
+            let size = read_u64(&mut reader)?;
+            let modified = (read_u64(&mut reader)?, read_u32(&mut reader)?);
+            let mut flags = [0];
+            reader.read_exact(&mut flags)?;
+            let trigrams = (0..read_len(&mut reader, 4)?)
+                .map(|_| read_u32(&mut reader))
+                .collect::<io::Result<_>>()?;
+            let entry = Entry {
+                size,
+                modified,
+                always: flags[0] & 1 == 1,
+                trigrams,
+            };
+            entries.insert(path, entry);
+        }
+        Ok(Index { entries })
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&len(self.entries.len())?.to_le_bytes())?;
+        for (path, entry) in &self.entries {
+            out.write_all(&len(path.len())?.to_le_bytes())?;
+            out.write_all(path.as_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&entry.modified.1.to_le_bytes())?;
+            out.write_all(&[u8::from(entry.always)])?;
+            out.write_all(&len(entry.trigrams.len())?.to_le_bytes())?;
+            for trigram in &entry.trigrams {
+                out.write_all(&trigram.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps the files of root that can match the query. Files that are
+    // new or changed since the index was built are kept too, the index
+    // can't tell anything about them
+    pub fn candidates(
+        &self,
+        root: &str,
+        files: Vec<PathBuf>,
+        query: &TrigramQuery,
+    ) -> Vec<PathBuf> {
+        files
+            .into_iter()
+            .filter(|file| {
+                let entry = key(root, file).and_then(|key| self.entries.get(&key));
+                match (entry, fs::metadata(file)) {
+                    (Some(entry), Ok(metadata)) if entry.is_fresh(&metadata) => {
+                        entry.always || query.allows(&entry.trigrams)
+                    }
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+}
+
+// Builds the index of the directory root, or brings it up to date: only
+// the files that are new or changed since the last build are read
+pub fn build(root: &str) -> Result<BuildStats, LilgrepError> {
+    if !Path::new(root).is_dir() {
+        // A missing directory is reported as missing
+        fs::metadata(root).map_err(|err| LilgrepError::io(root, err))?;
+        return Err(LilgrepError::Index {
+            path: root.to_string(),
+            problem: "only directories can be indexed".to_string(),
+        });
+    }
+    // An index that can't be read is built again from scratch
+    let mut old = Index::load(root).unwrap_or_default();
+    let mut index = Index::default();
+    let mut stats = BuildStats::default();
+    for file in walk::files(root, &WalkOptions::default())? {
+        // A path that isn't UTF-8 stays out of the index, so it's always
+        // searched
+        let Some(key) = key(root, &file) else {
+            continue;
+        };
+        let label = file.display().to_string();
+        let entry = match (old.entries.remove(&key), fs::metadata(&file)) {
+            (Some(entry), Ok(metadata)) if entry.is_fresh(&metadata) => entry,
+            (_, metadata) => {
+                stats.updated += 1;
+                match metadata.and_then(|metadata| Ok(Entry::new(&metadata, &fs::read(&file)?))) {
+                    Ok(entry) => entry,
+                    // Like a search, a file that can't be read is only
+                    // reported. It's left out, so it's always searched
+                    Err(err) => {
+                        eprintln!("lilgrep: {}", LilgrepError::io(&label, err));
+                        continue;
+                    }
+                }
+            }
+        };
+        index.entries.insert(key, entry);
+    }
+    stats.files = index.entries.len();
+    stats.removed = old.entries.len();
+
+    // Written next to the old index and then moved over it, so a search
+    // never sees half an index
+    let path = Path::new(root).join(INDEX_FILE);
+    let label = path.display().to_string();
+    let write = || -> io::Result<()> {
+        let mut out = BufWriter::new(NamedTempFile::new_in(root)?);
+        index.write(&mut out)?;
+        let temporary = out.into_inner().map_err(|err| err.into_error())?;
+        temporary.persist(&path).map_err(|err| err.error)?;
+        Ok(())
+    };
+    write().map_err(|err| LilgrepError::io(&label, err))?;
+    Ok(stats)
+}
+
+// The trigrams a file must have to have a match. Each alternative comes
+// from one of the literals a match can start with, and a file must have
+// every trigram of at least one of them
+#[derive(Debug, PartialEq)]
+pub struct TrigramQuery {
+    // None when the patterns don't tell anything, every file is a candidate
+    alternatives: Option<Vec<Vec<u32>>>,
+}
+
+impl TrigramQuery {
+    pub fn new(patterns: &[String], options: &MatcherOptions) -> TrigramQuery {
+        // A fuzzy match may have none of the trigrams of its pattern
+        if options.fuzzy.is_some() {
+            return TrigramQuery::everything();
+        }
+        let mut alternatives = Vec::new();
+        for pattern in patterns {
+            if !options.regex {
+                alternatives.push(trigrams(pattern));
+                continue;
+            }
+            match literals(pattern, options.ignore_case) {
+                Some(literals) => alternatives.extend(literals.iter().map(|l| trigrams(l))),
+                None => return TrigramQuery::everything(),
+            }
+        }
+        TrigramQuery {
+            alternatives: Some(alternatives),
+        }
+    }
+
+    // Doesn't narrow anything down
+    pub fn everything() -> TrigramQuery {
+        TrigramQuery { alternatives: None }
+    }
+
+    fn allows(&self, trigrams: &[u32]) -> bool {
+        match &self.alternatives {
+            None => true,
+            Some(alternatives) => alternatives.iter().any(|alternative| {
+                alternative
+                    .iter()
+                    .all(|trigram| trigrams.binary_search(trigram).is_ok())
+            }),
+        }
+    }
+}
+
+// The literals every match of a regular expression starts with, or None
+// when there are too many of them, like for \w+
+fn literals(pattern: &str, ignore_case: bool) -> Option<Vec<String>> {
+    let hir = ParserBuilder::new()
+        .case_insensitive(ignore_case)
+        .build()
+        .parse(pattern)
+        .ok()?;
+    let seq = Extractor::new().extract(&hir);
+    let literals = seq
+        .literals()?
+        .iter()
+        .map(|literal| {
+            // A long literal can be cut in the middle of a char, what
+            // comes before the cut is still a literal of every match
+            let bytes = literal.as_bytes();
+            match str::from_utf8(bytes) {
+                Ok(text) => text.to_string(),
+                Err(err) => String::from_utf8_lossy(&bytes[..err.valid_up_to()]).into_owned(),
+            }
+        })
+        .collect();
+    Some(literals)
+}
+
+// Sorted and without repeats. The text is case folded first, so the same
+// index works with and without -i
+fn trigrams(text: &str) -> Vec<u32> {
+    let folded = fold_case(text);
+    let unique: HashSet<u32> = folded
+        .as_bytes()
+        .windows(3)
+        .map(|bytes| u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]))
+        .collect();
+    let mut trigrams: Vec<u32> = unique.into_iter().collect();
+    trigrams.sort_unstable();
+    trigrams
+}
+
+// The path of a file inside root, the way the index stores it
+fn key(root: &str, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(root).ok()?;
+    let names: Vec<&str> = relative
+        .components()
+        .map(|name| name.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    Some(names.join("/"))
+}
+
+fn modified(metadata: &Metadata) -> (u64, u32) {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+fn len(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| io::Error::other("too big for the index"))
+}
+
+fn corrupted(problem: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, problem)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// A count of items of size bytes each, that must fit in what's left
+fn read_len(reader: &mut &[u8], size: usize) -> io::Result<usize> {
+    let count = read_u32(reader)? as usize;
+    if count.saturating_mul(size) > reader.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(count)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn options(regex: bool, ignore_case: bool) -> MatcherOptions {
+        MatcherOptions {
+            regex,
+            ignore_case,
+            ..MatcherOptions::default()
+        }
+    }
+
+    fn candidates(root: &Path, pattern: &str, options: MatcherOptions) -> Vec<String> {
+        let root = root.to_str().unwrap();
+        let index = Index::load(root).unwrap();
+        let files = walk::files(root, &WalkOptions::default()).unwrap();
+        let query = TrigramQuery::new(&[pattern.to_string()], &options);
+        let found = index.candidates(root, files, &query);
+        found.iter().filter_map(|file| key(root, file)).collect()
+    }
+
+    #[test]
+    fn queries() {
+        let text = trigrams("fn tasi() { STRASSE }");
+        let allows = |pattern: &str, regex| {
+            TrigramQuery::new(&[pattern.to_string()], &options(regex, true)).allows(&text)
+        };
+        assert!(allows("TASI", false));
+        assert!(allows("straße", false));
+        assert!(!allows("tasi_generated", false));
+        // Too short to say anything
+        assert!(allows("zz", false));
+        assert!(allows(r"fn \w+\(", true));
+        assert!(allows("(tasi|other)", true));
+        assert!(!allows("(other|another)", true));
+        assert_eq!(
+            TrigramQuery::everything(),
+            TrigramQuery::new(&[r"\w+".to_string()], &options(true, false))
+        );
+        let fuzzy = MatcherOptions {
+            fuzzy: Some(1),
+            ..MatcherOptions::default()
+        };
+        assert_eq!(
+            TrigramQuery::everything(),
+            TrigramQuery::new(&["tasi".to_string()], &fuzzy)
+        );
+    }
+
+    #[test]
+    fn round_trip_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "first tasi\n").unwrap();
+        let root = dir.path().to_str().unwrap();
+        assert!(matches!(Index::load(root), Err(LilgrepError::Index { .. })));
+        build(root).unwrap();
+
+        let mut bytes = Vec::new();
+        let index = Index::load(root).unwrap();
+        index.write(&mut bytes).unwrap();
+        assert_eq!(index, Index::read(bytes.as_slice()).unwrap());
+
+        // Another version, and half an index
+        bytes[4] = 9;
+        let err = Index::read(bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("version 9"));
+        bytes[4] = 1;
+        let err = Index::read(&bytes[..bytes.len() - 2]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        // Lengths past the end of the file, for a path and for trigrams
+        let path_len = 12;
+        let trigrams_len = path_len + 4 + "a.txt".len() + 8 + 8 + 4 + 1;
+        for at in [path_len, trigrams_len] {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let err = Index::read(&corrupt).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        }
+        fs::write(dir.path().join(INDEX_FILE), &bytes[..path_len + 4]).unwrap();
+        let err = Index::load(root).unwrap_err();
+        assert!(err.to_string().ends_with("corrupted index, build it again"));
+    }
+
+    #[test]
+    fn incremental_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "pub fn tasi() {}\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "nothing here\n").unwrap();
+        fs::write(dir.path().join("old.txt"), "tasi was here\n").unwrap();
+        let stats = build(root).unwrap();
+        assert_eq!((3, 3, 0), (stats.files, stats.updated, stats.removed));
+        assert_eq!(
+            vec!["old.txt", "src/lib.rs"],
+            candidates(dir.path(), "tasi", options(false, false))
+        );
+
+        // Nothing changed, nothing is read
+        let stats = build(root).unwrap();
+        assert_eq!((3, 0, 0), (stats.files, stats.updated, stats.removed));
+
+        // A file changed after the build is a candidate before the index
+        // knows it, and one that was removed is forgotten
+        thread::sleep(Duration::from_millis(10));
+        fs::write(dir.path().join("notes.txt"), "now tasi is here too\n").unwrap();
+        fs::remove_file(dir.path().join("old.txt")).unwrap();
+        assert_eq!(
+            vec!["notes.txt", "src/lib.rs"],
+            candidates(dir.path(), "tasi", options(false, false))
+        );
+        let stats = build(root).unwrap();
+        assert_eq!((2, 1, 1), (stats.files, stats.updated, stats.removed));
+        assert_eq!(
+            vec!["notes.txt"],
+            candidates(dir.path(), "NOW", options(false, true))
+        );
+    }
+}
@@ -0,0 +1,82 @@
+// The --json output: one JSON object per line, each one an event of the
+// search. The schema is documented in the README and must stay stable,
+// tools parse it. Every object has a "type" and a "data" field
+use crate::printer::Stats;
+use serde_json::{json, Value};
+use std::ops::Range;
+
+// Comes before the first line printed for a file
+pub fn begin(path: &str) -> String {
+    event("begin", json!({ "path": path }))
+}
+
+// A selected line. Each submatch has the matched text and where it is
+// inside the line, in bytes. With --fuzzy it also has its distance
+pub fn matched(
+    path: &str,
+    line_number: usize,
+    absolute_offset: usize,
+    line: &str,
+    spans: &[Range<usize>],
+    distances: &[usize],
+) -> String {
+    let submatches: Vec<Value> = spans
+        .iter()
+        .enumerate()
+        .map(|(index, span)| {
+            let mut submatch =
+                json!({ "match": &line[span.clone()], "start": span.start, "end": span.end });
+            if let Some(distance) = distances.get(index) {
+                submatch["distance"] = json!(distance);
+            }
+            submatch
+        })
+        .collect();
+    event(
+        "match",
+        json!({
+            "path": path,
+            "line_number": line_number,
+            "absolute_offset": absolute_offset,
+            "line": line,
+            "submatches": submatches,
+        }),
+    )
+}
+
+// A line printed only because it's around a match
+pub fn context(path: &str, line_number: usize, absolute_offset: usize, line: &str) -> String {
+    event(
+        "context",
+        json!({
+            "path": path,
+            "line_number": line_number,
+            "absolute_offset": absolute_offset,
+            "line": line,
+        }),
+    )
+}
+
+// Comes after the last line printed for a file
+pub fn end(path: &str, matched_lines: usize) -> String {
+    event(
+        "end",
+        json!({ "path": path, "matched_lines": matched_lines }),
+    )
+}
+
+// The last line of the whole output
+pub fn summary(stats: &Stats) -> String {
+    event(
+        "summary",
+        json!({
+            "files_searched": stats.files_searched,
+            "files_with_matches": stats.files_with_matches,
+            "matched_lines": stats.matched_lines,
+        }),
+    )
+}
+
+fn event(kind: &str, data: Value) -> String {
+    json!({ "type": kind, "data": data }).to_string()
+}
@@ -1,56 +1,1452 @@
-use std::{error::Error, fs};
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, LineWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+};
+
+mod args;
+mod compress;
+mod encoding;
+mod error;
+mod follow;
+mod fuzzy;
+mod index;
+mod json;
+mod matcher;
+mod pool;
+mod printer;
+mod query;
+mod replace;
+mod searcher;
+mod sink;
+mod stream;
+mod walk;
+
+pub use args::ArgError;
+use args::Given;
+pub use encoding::Encoding;
+pub use error::LilgrepError;
+use follow::Follow;
+use index::{Index, TrigramQuery};
+pub use matcher::{Matcher, MatcherOptions};
+use pool::ThreadPool;
+pub use printer::{ColorChoice, OutputMode};
+use printer::{Printer, Stats};
+pub use query::QueryError;
+use query::{Query, QueryMatch, QueryScope};
+pub use searcher::{MmapChoice, Searcher, SearcherBuilder};
+pub use sink::{Sink, SinkLine};
+pub use stream::SearchOptions;
+pub use walk::WalkOptions;
 
 #[derive(Debug)]
 pub struct Config {
     file_path: String,
-    query: String,
+    // A line matches if any of the patterns matches. It's the query given
+    // as an argument or every pattern given with -e
+    patterns: Vec<String>,
+    // Files with one pattern per line, given with -f. They're only read
+    // by run, so reading errors are reported like any other file error
+    pattern_files: Vec<String>,
+    // When true the query is compiled as a regular expression
+    regex: bool,
+    // When true upper and lower case letters are considered the same
+    ignore_case: bool,
+    // -w and -x, matches must be whole words or whole lines
+    word: bool,
+    whole_line: bool,
+    // --fuzzy, matches can be this many edits away from the patterns
+    fuzzy: Option<usize>,
+    // Globs and ignore files that filter the files found when file_path
+    // is a directory
+    walk: WalkOptions,
+    // -n prints the line number and -b the byte offset of each line
+    line_number: bool,
+    byte_offset: bool,
+    // Lines of context printed before and after each match
+    before_context: usize,
+    after_context: usize,
+    // -v selects the lines that don't match
+    invert: bool,
+    // -U, the matcher sees the whole input instead of a line at a time
+    multiline: bool,
+    // -m stops each file after this many selected lines
+    max_count: Option<usize>,
+    // -c, -l, -L or --json print something else than the lines
+    mode: OutputMode,
+    // --encoding, how the bytes of the files become text
+    encoding: Encoding,
+    // -a, binary files are searched and printed like text files
+    text: bool,
+    // -z, compressed files are decompressed before the search
+    decompress: bool,
+    // -j, how many files are searched at the same time
+    threads: usize,
+    // --color, already decided between on and off
+    color: bool,
+    // --replace, the template that takes the place of each match
+    replace: Option<String>,
+    // --write, the replacements go to the files instead of a preview
+    write: bool,
+    // --indexed, the index of the directory picks the files to search
+    indexed: bool,
+    // --follow, the file is searched as it grows, like tail -f
+    follow: bool,
+    // --query, a boolean query of plain text terms instead of patterns,
+    // that must hold for a line or for a whole file
+    query: Option<String>,
+    query_scope: QueryScope,
+}
+
+// What the command line asks for: a search, the usual, or building the
+// index of a directory with lilgrep index build DIR
+#[derive(Debug)]
+pub enum Command {
+    // Boxed, a Config is much bigger than a path
+    Search(Box<Config>),
+    BuildIndex(String),
+}
+
+impl Command {
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Command, ArgError> {
+        let args: Vec<String> = args.collect();
+        // A directory called index can still be searched as ./index
+        if args.get(1).is_some_and(|arg| arg == "index")
+            && args.get(2).is_some_and(|arg| arg == "build")
+        {
+            let parsed = args::parse(args.into_iter().skip(3))?;
+            if let Some(given) = parsed.options.first() {
+                return match given.long {
+                    "help" => Err(ArgError::Help(args::help())),
+                    _ => Err(ArgError::UnexpectedArgument(given.written.clone())),
+                };
+            }
+            let mut positional = parsed.positional.into_iter();
+            let dir = positional.next().ok_or(ArgError::MissingArgument("DIR"))?;
+            if let Some(extra) = positional.next() {
+                return Err(ArgError::UnexpectedArgument(extra));
+            }
+            return Ok(Command::BuildIndex(dir));
+        }
+        Config::build(args.into_iter()).map(|config| Command::Search(Box::new(config)))
+    }
+}
+
+// Where a match was found. line_number starts at 1 and byte_offset is where
+// the line starts inside the searched content. span is the part of the line
+// that matched the query
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+    pub span: Range<usize>,
+    // How many edits away from the query the match is, always 0 but
+    // for search_fuzzy
+    pub distance: usize,
 }
 
 impl Config {
     // Function name is build because when a programmer uses the new function
     // they expects the function to never fail
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Config, ArgError> {
+        // Only the presence of the variable matters, not its value, not
+        // even if it's valid UTF-8
+        Config::build_with_env(args, env::var_os("IGNORE_CASE").is_some())
+    }
+
+    // Same as build, with ignore_case_env telling if IGNORE_CASE is set
+    // instead of looking at the environment
+    pub fn build_with_env(
+        mut args: impl Iterator<Item = String>,
+        ignore_case_env: bool,
+    ) -> Result<Config, ArgError> {
         // The zero argument is the executable file
         args.next();
+        let parsed = args::parse(args)?;
+
+        let mut regex = false;
+        let mut word = false;
+        let mut whole_line = false;
+        let mut fuzzy = None;
+        // None means no flag was given, so the environment decides
+        let mut ignore_case = None;
+        let mut walk = WalkOptions::default();
+        let mut line_number = false;
+        let mut byte_offset = false;
+        let mut before_context = 0;
+        let mut after_context = 0;
+        let mut patterns = Vec::new();
+        let mut pattern_files = Vec::new();
+        let mut invert = false;
+        let mut multiline = false;
+        let mut max_count = None;
+        let mut mode = OutputMode::Lines;
+        let mut encoding = Encoding::Auto;
+        let mut text = false;
+        let mut decompress = false;
+        // One thread per CPU, unless told otherwise
+        let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut color = ColorChoice::Auto;
+        let mut replace = None;
+        let mut write = false;
+        let mut indexed = false;
+        let mut follow = false;
+        let mut query = None;
+        let mut query_scope = QueryScope::Line;
+        // -t and -T as given, checked once every --type-add is known
+        let mut type_names = Vec::new();
+        let mut type_list = false;
+        // The options are applied in the order they were given, so the
+        // last one wins when they disagree, like -c and -l
+        for given in parsed.options {
+            match given.long {
+                "help" => return Err(ArgError::Help(args::help())),
+                "version" => return Err(ArgError::Version(args::version())),
+                "regexp" => patterns.push(given.value),
+                "file" => pattern_files.push(given.value),
+                "regex" => regex = true,
+                "fixed-strings" => regex = false,
+                "word-regexp" => word = true,
+                "line-regexp" => whole_line = true,
+                "fuzzy" => fuzzy = Some(number(&given, "a number of edits")?),
+                "ignore-case" => ignore_case = Some(true),
+                "case-sensitive" => ignore_case = Some(false),
+                "invert-match" => invert = true,
+                "multiline" => multiline = true,
+                "max-count" => max_count = Some(number(&given, "a number of lines")?),
+                "line-number" => line_number = true,
+                "byte-offset" => byte_offset = true,
+                "after-context" => after_context = number(&given, "a number of lines")?,
+                "before-context" => before_context = number(&given, "a number of lines")?,
+                "context" => {
+                    after_context = number(&given, "a number of lines")?;
+                    before_context = after_context;
+                }
+                "count" => mode = OutputMode::Count,
+                "files-with-matches" => mode = OutputMode::FilesWithMatches,
+                "files-without-match" => mode = OutputMode::FilesWithoutMatch,
+                "json" => mode = OutputMode::Json,
+                "encoding" => {
+                    encoding = Encoding::parse(&given.value).ok_or_else(|| {
+                        invalid(&given, "auto, utf-8, utf-16le, utf-16be or latin1")
+                    })?
+                }
+                "text" => text = true,
+                "search-zip" => decompress = true,
+                // A lone --color is the same as --color=auto
+                "color" if given.value.is_empty() => color = ColorChoice::Auto,
+                "color" => {
+                    color = ColorChoice::parse(&given.value)
+                        .ok_or_else(|| invalid(&given, "auto, always or never"))?
+                }
+                "include" => walk.include.push(given.value),
+                "exclude" => walk.exclude.push(given.value),
+                "no-ignore" => walk.no_ignore = true,
+                "hidden" => walk.hidden = true,
+                "type" => {
+                    walk.types.push(given.value.clone());
+                    type_names.push(given);
+                }
+                "type-not" => {
+                    walk.types_not.push(given.value.clone());
+                    type_names.push(given);
+                }
+                "type-add" if walk::is_type_definition(&given.value) => {
+                    walk.type_add.push(given.value)
+                }
+                "type-add" => return Err(invalid(&given, "NAME:GLOB, like rust:*.rs")),
+                "type-list" => type_list = true,
+                "threads" => match number(&given, "a number of threads above 0")? {
+                    0 => return Err(invalid(&given, "a number of threads above 0")),
+                    value => threads = value,
+                },
+                "replace" => replace = Some(given.value),
+                "write" => write = true,
+                "indexed" => indexed = true,
+                "follow" => follow = true,
+                "query" => query = Some(given.value),
+                "query-scope" => {
+                    query_scope = QueryScope::parse(&given.value)
+                        .ok_or_else(|| invalid(&given, "line or file"))?
+                }
+                // Every option in args::SPECS has its own branch
+                other => unreachable!("option --{other} isn't handled"),
+            }
+        }
+
+        if type_list {
+            return Err(ArgError::TypeList(walk::type_list(&walk.type_add)));
+        }
+        if let Some(unknown) = type_names
+            .iter()
+            .find(|given| !walk::is_type(&given.value, &walk.type_add))
+        {
+            return Err(invalid(unknown, "a type from --type-list"));
+        }
+
+        let mut positional = parsed.positional.into_iter();
         // the first argument is the file path
-        let file_path = match args.next() {
-            Some(value) => value,
-            None => return Err("Didn't get a file path"),
+        let file_path = positional.next().ok_or(ArgError::MissingArgument("PATH"))?;
+        // the second is the string to be found, unless the patterns
+        // were already given with -e or -f, or there's a --query
+        if patterns.is_empty() && pattern_files.is_empty() && query.is_none() {
+            patterns.push(
+                positional
+                    .next()
+                    .ok_or(ArgError::MissingArgument("QUERY"))?,
+            );
+        }
+        if let Some(extra) = positional.next() {
+            return Err(ArgError::UnexpectedArgument(extra));
+        }
+        if write && replace.is_none() {
+            return Err(ArgError::Conflict("--write", "--replace"));
+        }
+        if write && file_path == "-" {
+            return Err(ArgError::Incompatible("--write", "the standard input"));
+        }
+        // The fuzzy matcher only compares plain text, line by line
+        if fuzzy.is_some() && (regex || word || whole_line || multiline) {
+            return Err(ArgError::Incompatible("--fuzzy", "-E, -w, -x or -U"));
+        }
+        // The terms of a query are plain text, searched one line at a time
+        if query.is_some() {
+            let unsupported = [
+                (
+                    !patterns.is_empty() || !pattern_files.is_empty(),
+                    "-e or -f",
+                ),
+                (regex || word || whole_line, "-E, -w or -x"),
+                (multiline, "-U"),
+                (fuzzy.is_some(), "--fuzzy"),
+                (before_context > 0 || after_context > 0, "-A, -B or -C"),
+                (replace.is_some(), "--replace"),
+                (follow, "--follow"),
+                (indexed, "--indexed"),
+            ];
+            if let Some((_, with)) = unsupported.iter().find(|(given, _)| *given) {
+                return Err(ArgError::Incompatible("--query", with));
+            }
+        } else if query_scope == QueryScope::File {
+            return Err(ArgError::Conflict("--query-scope", "--query"));
+        }
+        // A whole file has no line to print, only its name
+        if query_scope == QueryScope::File {
+            match mode {
+                OutputMode::Lines => mode = OutputMode::FilesWithMatches,
+                OutputMode::Count | OutputMode::Json => {
+                    return Err(ArgError::Incompatible("--query-scope=file", "-c or --json"))
+                }
+                OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {}
+            }
+        }
+        // Following never ends, so it can only print lines as they come
+        if follow {
+            if file_path == "-" {
+                return Err(ArgError::Incompatible("--follow", "the standard input"));
+            }
+            if replace.is_some() {
+                return Err(ArgError::Incompatible("--follow", "--replace"));
+            }
+            if multiline {
+                return Err(ArgError::Incompatible("--follow", "-U"));
+            }
+            if matches!(
+                mode,
+                OutputMode::Count | OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch
+            ) {
+                return Err(ArgError::Incompatible("--follow", "-c, -l or -L"));
+            }
+        }
+        // The flag always wins over the IGNORE_CASE environment variable
+        let ignore_case = ignore_case.unwrap_or(ignore_case_env);
+        Ok(Config {
+            file_path,
+            patterns,
+            pattern_files,
+            regex,
+            ignore_case,
+            word,
+            whole_line,
+            fuzzy,
+            walk,
+            line_number,
+            byte_offset,
+            before_context,
+            after_context,
+            invert,
+            multiline,
+            max_count,
+            mode,
+            encoding,
+            text,
+            decompress,
+            threads,
+            color: color.enabled(),
+            replace,
+            write,
+            indexed,
+            follow,
+            query,
+            query_scope,
+        })
+    }
+
+    // The part of the config that drives the search of each file
+    fn search_options(&self) -> SearchOptions {
+        // To know if a file has a match or not, a single one is enough
+        let max_count = match self.mode {
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => Some(1),
+            _ => self.max_count,
         };
-        // the rest are string to be found
-        let query = match args.next() {
-            Some(value) => value,
-            None => return Err("Didn't get a query"),
+        SearchOptions {
+            before_context: self.before_context,
+            after_context: self.after_context,
+            invert: self.invert,
+            max_count,
+            multiline: self.multiline,
+        }
+    }
+
+    // How the patterns become a Matcher
+    fn matcher_options(&self) -> MatcherOptions {
+        MatcherOptions {
+            regex: self.regex,
+            ignore_case: self.ignore_case,
+            word: self.word,
+            whole_line: self.whole_line,
+            multiline: self.multiline,
+            fuzzy: self.fuzzy,
+        }
+    }
+
+    // The searcher every file goes through, matcher is the compiled query
+    // With many files, reading them beats mapping them, see MmapChoice
+    fn searcher(&self, matcher: Matcher, many: bool) -> Searcher {
+        let mmap = if many {
+            MmapChoice::Never
+        } else {
+            MmapChoice::Auto
         };
-        Ok(Config { file_path, query })
+        Searcher::builder(matcher)
+            .options(self.search_options())
+            .encoding(self.encoding)
+            .binary_as_text(self.text)
+            .decompress(self.decompress)
+            .mmap(mmap)
+            .build()
     }
 }
 
-// Box<dyn Error> means an implementation of Error
-// dyn is a short for dynamic
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let content = fs::read_to_string(config.file_path)?;
-    for line in search(&content, &config.query) {
-        println!("{line}");
+// Parses the value of the options that take a number
+fn number(given: &Given, expected: &'static str) -> Result<usize, ArgError> {
+    given.value.parse().map_err(|_| invalid(given, expected))
+}
+
+fn invalid(given: &Given, expected: &'static str) -> ArgError {
+    ArgError::InvalidValue {
+        option: given.written.clone(),
+        value: given.value.clone(),
+        expected,
     }
-    Ok(())
 }
 
-fn search<'a>(content: &'a str, query: &'a str) -> Vec<&'a str> {
-    content
-        .lines()
-        .filter(|line| line.contains(query))
+// Returns true when something was found, so main can choose the exit
+// status. Every error says what went wrong and with which file, see
+// LilgrepError
+pub fn run(config: Config) -> Result<bool, LilgrepError> {
+    if let Some(text) = &config.query {
+        let query = Query::parse(text).map_err(|err| LilgrepError::Pattern {
+            pattern: text.clone(),
+            source: err.into(),
+        })?;
+        return run_query(&config, &query, BufWriter::new(io::stdout().lock()));
+    }
+    // Compiles the pattern once, before touching the file, so an invalid
+    // regular expression is reported right away
+    let patterns = load_patterns(&config)?;
+    let matcher = Matcher::with_options(&patterns, &config.matcher_options())?;
+    if config.follow {
+        return search_follow(&config, &config.searcher(matcher, false));
+    }
+    // Printing line by line to the standard output is slow, the
+    // BufWriter sends bigger chunks at once
+    let out = BufWriter::new(io::stdout().lock());
+
+    if let Some(template) = &config.replace {
+        // Fixed strings with -w or -x, but without -i, go through the
+        // regex engine, the template must still be plain text for them
+        if !config.regex && matcher.is_regex() {
+            let template = template.replace('$', "$$");
+            return run_replace(&config, &matcher, &template, out);
+        }
+        return run_replace(&config, &matcher, template, out);
+    }
+
+    // "-" is the standard input, like in grep: cat log | lilgrep - ERROR
+    // A single file is searched right into the standard output too,
+    // there's nothing to split between threads
+    if config.file_path == "-" || !Path::new(&config.file_path).is_dir() {
+        let searcher = config.searcher(matcher, false);
+        let (found, stats, mut out) =
+            search_one(&config.file_path, &searcher, &config, false, out)?;
+        printer::write_summary(&mut out, config.mode, &stats).map_err(LilgrepError::Output)?;
+        out.flush().map_err(LilgrepError::Output)?;
+        return Ok(found);
+    }
+
+    let searcher = config.searcher(matcher, true);
+    let mut files = walk::files(&config.file_path, &config.walk)?;
+    if config.indexed {
+        let index = Index::load(&config.file_path)?;
+        // -v, -c and -L print something for the files without a match
+        // too, and other encodings don't have the bytes the index saw
+        let narrows = !config.invert
+            && !matches!(
+                config.mode,
+                OutputMode::Count | OutputMode::FilesWithoutMatch
+            )
+            && matches!(config.encoding, Encoding::Auto | Encoding::Utf8);
+        let query = if narrows {
+            TrigramQuery::new(&patterns, &config.matcher_options())
+        } else {
+            TrigramQuery::everything()
+        };
+        files = index.candidates(&config.file_path, files, &query);
+    }
+    let mut out = out;
+    let mode = config.mode;
+    let (found, stats, skipped) =
+        search_files(files, Arc::new(config), Arc::new(searcher), &mut out)
+            .map_err(LilgrepError::Output)?;
+    printer::write_summary(&mut out, mode, &stats).map_err(LilgrepError::Output)?;
+    out.flush().map_err(LilgrepError::Output)?;
+    skipped.result(found)
+}
+
+// The files of a directory that couldn't be searched. Each one is
+// reported right away and the search goes on with the other ones, but
+// the exit status still tells about them at the end, like in grep
+#[derive(Debug, Default)]
+struct Skipped {
+    files: usize,
+    first: Option<LilgrepError>,
+}
+
+impl Skipped {
+    fn report(&mut self, err: LilgrepError) {
+        eprintln!("lilgrep: {err}");
+        self.files += 1;
+        self.first.get_or_insert(err);
+    }
+
+    // What the search gives back: if something was found, unless a file
+    // had to be skipped
+    fn result(self, found: bool) -> Result<bool, LilgrepError> {
+        match self.first {
+            None => Ok(found),
+            Some(first) => Err(LilgrepError::Skipped {
+                files: self.files,
+                first: Box::new(first),
+            }),
+        }
+    }
+}
+
+// lilgrep index build DIR: builds the index of DIR, or updates it
+pub fn build_index(dir: &str) -> Result<bool, LilgrepError> {
+    let stats = index::build(dir)?;
+    println!(
+        "{dir}: {} files indexed, {} new or changed, {} removed",
+        stats.files, stats.updated, stats.removed
+    );
+    Ok(true)
+}
+
+// The --replace mode: shows a preview of the changes of every file or,
+// with --write, changes the files
+fn run_replace<W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    template: &str,
+    mut out: W,
+) -> Result<bool, LilgrepError> {
+    // Config::build already refused --write with the standard input
+    if config.file_path == "-" {
+        let changes =
+            replace::replace_reader(io::stdin().lock(), matcher, template, &mut io::sink())
+                .map_err(|err| LilgrepError::io("(standard input)", err))?;
+        replace::print_preview(&mut out, "(standard input)", &changes)
+            .map_err(LilgrepError::Output)?;
+        out.flush().map_err(LilgrepError::Output)?;
+        return Ok(!changes.is_empty());
+    }
+
+    let recursive = Path::new(&config.file_path).is_dir();
+    let mut found = false;
+    let mut skipped = Skipped::default();
+    for path in walk::files(&config.file_path, &config.walk)? {
+        let label = path.display().to_string();
+        match replace::replace_file(&path, matcher, template, config.write) {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) if config.write => {
+                found = true;
+                let lines = if changes.len() == 1 { "line" } else { "lines" };
+                writeln!(out, "{label}: {} {lines} changed", changes.len())
+                    .map_err(LilgrepError::Output)?;
+            }
+            Ok(changes) => {
+                found = true;
+                replace::print_preview(&mut out, &label, &changes).map_err(LilgrepError::Output)?;
+            }
+            // A single unreadable file inside a directory is only reported,
+            // the other ones are still changed
+            Err(err) if recursive => skipped.report(LilgrepError::io(&label, err)),
+            Err(err) => return Err(LilgrepError::io(&label, err)),
+        }
+    }
+    out.flush().map_err(LilgrepError::Output)?;
+    skipped.result(found)
+}
+
+// Searches a single file, or the standard input when path is "-",
+// printing it into out. Gives back if something was found, the totals of
+// the search and out itself
+fn search_one<W: Write>(
+    path: &str,
+    searcher: &Searcher,
+    config: &Config,
+    with_path: bool,
+    out: W,
+) -> Result<(bool, Stats, W), LilgrepError> {
+    let mut printer = Printer::new(config, with_path, out);
+    let count = if path == "-" {
+        let label = "(standard input)";
+        printer.begin_file(label);
+        searcher
+            .search_reader(io::stdin().lock(), &mut printer)
+            .map_err(|err| LilgrepError::io(label, err))?
+    } else {
+        printer.begin_file(path);
+        searcher.search_path(path, &mut printer)?
+    };
+    let found = printer.end_file(count).map_err(LilgrepError::Output)?;
+    Ok((found, printer.stats(), printer.into_inner()))
+}
+
+// The --query mode. Each file is read whole and the query is evaluated
+// with the plain search, one file after the other
+fn run_query<W: Write>(config: &Config, query: &Query, out: W) -> Result<bool, LilgrepError> {
+    let recursive = config.file_path != "-" && Path::new(&config.file_path).is_dir();
+    let files = if recursive {
+        walk::files(&config.file_path, &config.walk)?
+    } else {
+        vec![PathBuf::from(&config.file_path)]
+    };
+    let max_count = config.search_options().max_count.unwrap_or(usize::MAX);
+    let mut printer = Printer::new(config, recursive, out);
+    let mut found = false;
+    let mut skipped = Skipped::default();
+    for path in files {
+        let label = match path.to_str() {
+            Some("-") => "(standard input)".to_string(),
+            _ => path.display().to_string(),
+        };
+        let (content, binary) = match read_text(&path, config) {
+            Ok(text) => text,
+            Err(err) if recursive => {
+                skipped.report(LilgrepError::io(&label, err));
+                continue;
+            }
+            Err(err) => return Err(LilgrepError::io(&label, err)),
+        };
+        printer.begin_file(&label);
+        if binary {
+            printer.binary().map_err(LilgrepError::Output)?;
+        }
+        let count = match config.query_scope {
+            QueryScope::File => {
+                usize::from(query.holds_in(&content, config.ignore_case) != config.invert)
+            }
+            QueryScope::Line => {
+                let mut selected = query.lines(&content, config.ignore_case);
+                if config.invert {
+                    // The lines the query doesn't hold for
+                    let held: Vec<usize> = selected.iter().map(|found| found.line_number).collect();
+                    selected = numbered_lines(&content)
+                        .filter(|(line_number, _, _)| held.binary_search(line_number).is_err())
+                        .map(|(line_number, byte_offset, line)| QueryMatch {
+                            line_number,
+                            byte_offset,
+                            line,
+                            spans: Vec::new(),
+                        })
+                        .collect();
+                }
+                let mut count = 0;
+                for found in selected.iter().take(max_count) {
+                    count += 1;
+                    let line = SinkLine {
+                        line_number: found.line_number,
+                        byte_offset: found.byte_offset,
+                        line: found.line,
+                        spans: &found.spans,
+                        distances: &[],
+                    };
+                    if !printer.matched(&line).map_err(LilgrepError::Output)? {
+                        break;
+                    }
+                }
+                count
+            }
+        };
+        found |= printer.end_file(count).map_err(LilgrepError::Output)?;
+    }
+    let stats = printer.stats();
+    let mut out = printer.into_inner();
+    printer::write_summary(&mut out, config.mode, &stats).map_err(LilgrepError::Output)?;
+    out.flush().map_err(LilgrepError::Output)?;
+    skipped.result(found)
+}
+
+// The whole text of a file, or of the standard input for "-", read the
+// same way a search reads it. Also tells if it looks like a binary file
+fn read_text(path: &Path, config: &Config) -> io::Result<(String, bool)> {
+    let input: Box<dyn BufRead> = if path.to_str() == Some("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+    let input = if config.decompress {
+        compress::decompress(input)?
+    } else {
+        input
+    };
+    let mut reader = encoding::decode(input, config.encoding)?;
+    let binary = !config.text && stream::is_binary(&mut reader)?;
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+    Ok((String::from_utf8_lossy(&content).into_owned(), binary))
+}
+
+// The --follow mode: searches the file, then every line appended to it,
+// until killed or until -m lines were selected
+fn search_follow(config: &Config, searcher: &Searcher) -> Result<bool, LilgrepError> {
+    let path = &config.file_path;
+    if Path::new(path).is_dir() {
+        return Err(ArgError::Incompatible("--follow", "a directory").into());
+    }
+    // Each line is written as soon as it's found, there may be hours
+    // before the next one
+    let out = LineWriter::new(io::stdout().lock());
+    let mut printer = Printer::new(config, false, out);
+    printer.begin_file(path);
+    let follow =
+        Follow::open(Path::new(path), follow::POLL).map_err(|err| LilgrepError::io(path, err))?;
+    let count = searcher
+        .search_reader(BufReader::new(follow), &mut printer)
+        .map_err(|err| LilgrepError::io(path, err))?;
+    let found = printer.end_file(count).map_err(LilgrepError::Output)?;
+    printer.into_inner().flush().map_err(LilgrepError::Output)?;
+    Ok(found)
+}
+
+// What a worker sends back after searching a file: its position in the
+// list of files and what search_one gave back
+type FileResult = (usize, Result<(bool, Stats, Vec<u8>), LilgrepError>);
+
+// Searches the files in parallel, with config.threads workers, and
+// gives back the files that couldn't be searched too. Each file is
+// printed into its own buffer and the buffers are written to out in the
+// same order the files came in, so the output is always the same no
+// matter which thread ends first
+fn search_files<W: Write>(
+    files: Vec<PathBuf>,
+    config: Arc<Config>,
+    searcher: Arc<Searcher>,
+    out: &mut W,
+) -> io::Result<(bool, Stats, Skipped)> {
+    let (sender, receiver) = mpsc::channel::<FileResult>();
+    let pool = ThreadPool::new(config.threads);
+    for (index, path) in files.into_iter().enumerate() {
+        let config = Arc::clone(&config);
+        let searcher = Arc::clone(&searcher);
+        let sender = sender.clone();
+        pool.execute(move || {
+            let label = path.display().to_string();
+            let result = search_one(&label, &searcher, &config, true, Vec::new());
+            // The receiver only goes away if writing the output failed,
+            // there's nothing left to do with the result then
+            let _ = sender.send((index, result));
+        });
+    }
+    // Only the workers hold senders now, the loop below ends when the
+    // last of them is done
+    drop(sender);
+
+    // Lines printed around matches are split in groups by "--", the
+    // buffers of different files are groups of their own
+    let separated =
+        config.mode == OutputMode::Lines && (config.before_context > 0 || config.after_context > 0);
+    let mut written = false;
+    let mut found = false;
+    let mut stats = Stats::default();
+    let mut skipped = Skipped::default();
+    // Results that arrived before the ones that come first in the list
+    let mut waiting = BTreeMap::new();
+    let mut next = 0;
+    for (index, result) in receiver {
+        waiting.insert(index, result);
+        while let Some(result) = waiting.remove(&next) {
+            next += 1;
+            match result {
+                Ok((file_found, file_stats, buffer)) => {
+                    found |= file_found;
+                    stats.add(file_stats);
+                    if buffer.is_empty() {
+                        continue;
+                    }
+                    if separated && written {
+                        writeln!(out, "{}", printer::group_separator(config.color))?;
+                    }
+                    out.write_all(&buffer)?;
+                    written = true;
+                }
+                // A single unreadable file inside a directory doesn't
+                // stop the search of the other ones
+                Err(err) => skipped.report(err),
+            }
+        }
+    }
+    Ok((found, stats, skipped))
+}
+
+// Every pattern given with -e and every line of the -f files
+fn load_patterns(config: &Config) -> Result<Vec<String>, LilgrepError> {
+    let mut patterns = config.patterns.clone();
+    for file in &config.pattern_files {
+        let text = fs::read_to_string(file).map_err(|err| LilgrepError::io(file, err))?;
+        patterns.extend(text.lines().map(String::from));
+    }
+    Ok(patterns)
+}
+
+pub fn search<'a>(content: &'a str, query: &str) -> Vec<Match<'a>> {
+    search_by(content, |line| {
+        line.find(query).map(|start| start..start + query.len())
+    })
+}
+
+// Same as search, but ignoring the letters case. Both sides are case folded,
+// so "STRASSE" finds "straße" and "ΣΑΣ" finds "σας"
+pub fn search_case_insensitive<'a>(content: &'a str, query: &str) -> Vec<Match<'a>> {
+    let matcher = Matcher::case_insensitive(query);
+    search_by(content, |line| matcher.find(line))
+}
+
+// Same as search, but the lines are tested against an already
+// compiled regular expression
+pub fn search_regex<'a>(content: &'a str, regex: &Regex) -> Vec<Match<'a>> {
+    search_by(content, |line| regex.find(line).map(|found| found.range()))
+}
+
+// Runs find on every line and keeps a Match for those where it found something
+fn search_by<'a>(
+    content: &'a str,
+    mut find: impl FnMut(&str) -> Option<Range<usize>>,
+) -> Vec<Match<'a>> {
+    numbered_lines(content)
+        .filter_map(|(line_number, byte_offset, line)| {
+            find(line).map(|span| Match {
+                line_number,
+                byte_offset,
+                line,
+                span,
+                distance: 0,
+            })
+        })
         .collect()
 }
 
+// Same as search, but a match can be up to max edits (inserted, removed
+// or replaced chars) away from the query. Fails when max is so big that
+// anything would match, or the query is too long for the fuzzy matcher
+pub fn search_fuzzy<'a>(
+    content: &'a str,
+    query: &str,
+    max: usize,
+) -> Result<Vec<Match<'a>>, LilgrepError> {
+    let options = MatcherOptions {
+        fuzzy: Some(max),
+        ..MatcherOptions::default()
+    };
+    let matcher = Matcher::with_options(&[query.to_string()], &options)?;
+    Ok(numbered_lines(content)
+        .filter_map(|(line_number, byte_offset, line)| {
+            let (spans, distances) = matcher.find_scored(line);
+            Some(Match {
+                line_number,
+                byte_offset,
+                line,
+                span: spans.first()?.clone(),
+                distance: distances[0],
+            })
+        })
+        .collect())
+}
+
+// Like str::lines, but also gives the line number and where the line starts
+fn numbered_lines(content: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut byte_offset = 0;
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(move |(index, raw)| {
+            let start = byte_offset;
+            byte_offset += raw.len();
+            let line = raw.strip_suffix('\n').unwrap_or(raw);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            (index + 1, start, line)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{fs::File, io::BufReader};
+
+    // Only the matching lines, for tests that don't care about the rest
+    fn lines<'a>(results: Vec<Match<'a>>) -> Vec<&'a str> {
+        results.into_iter().map(|result| result.line).collect()
+    }
 
     #[test]
     fn one_result() {
         let query = "tasi";
         let content = "some text here\nmore tasi there\nand some writting here";
-        assert_eq!(vec!["more tasi there"], search(content, query));
+        assert_eq!(vec!["more tasi there"], lines(search(content, query)));
+    }
+
+    #[test]
+    fn match_record() {
+        let content = "some text here\r\nmore tasi there\nand some writting here";
+        assert_eq!(
+            vec![Match {
+                line_number: 2,
+                byte_offset: 16,
+                line: "more tasi there",
+                span: 5..9,
+                distance: 0,
+            }],
+            search(content, "tasi")
+        );
+    }
+
+    #[test]
+    fn case_insensitive_span() {
+        let results = search_case_insensitive("die STRASSE ist", "straße");
+        assert_eq!(4..11, results[0].span);
+        let results = search_case_insensitive("eine Straße", "STRASSE");
+        assert_eq!("Straße", &results[0].line[results[0].span.clone()]);
+    }
+
+    #[test]
+    fn context_flags() {
+        let args = [
+            "lilgrep", "-n", "file.txt", "-C", "2", "tasi", "-A", "1", "-b",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.line_number && config.byte_offset);
+        assert_eq!((2, 1), (config.before_context, config.after_context));
+        let args = ["lilgrep", "file.txt", "tasi", "-A", "x"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let query = "rUsT";
+        let content = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.";
+        assert_eq!(
+            vec!["Rust:", "Trust me."],
+            lines(search_case_insensitive(content, query))
+        );
+    }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let content = "die straße\nDIE STRASSE\nΣΑΣ\nnothing";
+        assert_eq!(
+            vec!["die straße", "DIE STRASSE"],
+            lines(search_case_insensitive(content, "Straße"))
+        );
+        assert_eq!(vec!["ΣΑΣ"], lines(search_case_insensitive(content, "σας")));
+    }
+
+    #[test]
+    fn case_flags() {
+        let ignore_case = |args: &[&str], env: bool| {
+            let args = args.iter().map(|arg| arg.to_string());
+            Config::build_with_env(args, env).unwrap().ignore_case
+        };
+        assert!(ignore_case(&["lilgrep", "file.txt", "tasi", "-i"], false));
+        // IGNORE_CASE wins over the default
+        assert!(!ignore_case(&["lilgrep", "file.txt", "tasi"], false));
+        assert!(ignore_case(&["lilgrep", "file.txt", "tasi"], true));
+        // -s forces a case sensitive search even with IGNORE_CASE set
+        assert!(!ignore_case(&["lilgrep", "-s", "file.txt", "tasi"], true));
+        // The last flag wins
+        assert!(ignore_case(
+            &["lilgrep", "-s", "file.txt", "tasi", "-i"],
+            false
+        ));
+    }
+
+    #[test]
+    fn regex_result() {
+        let regex = Regex::new(r"^fn \w+").unwrap();
+        let content = "fn main() {\n    let fn_name = 1;\n}\nfn other() {}";
+        assert_eq!(
+            vec!["fn main() {", "fn other() {}"],
+            lines(search_regex(content, &regex))
+        );
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let args = ["lilgrep", "resources/file.txt", "-E", r"error\d{3"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        let err = run(config).unwrap_err();
+        assert!(matches!(err, LilgrepError::Pattern { .. }));
+        assert_eq!(3, err.exit_code());
+    }
+
+    #[test]
+    fn error_paths() {
+        let args = ["lilgrep", "resources/missing.txt", "Tasi"].map(String::from);
+        let err = run(Config::build(args.into_iter()).unwrap()).unwrap_err();
+        assert!(matches!(err, LilgrepError::NotFound { .. }));
+        assert!(err.to_string().starts_with("resources/missing.txt"));
+        assert!(std::error::Error::source(&err).is_some());
+
+        let args = ["lilgrep", "resources/tree", "-f", "resources/nope.txt"].map(String::from);
+        let err = run(Config::build(args.into_iter()).unwrap()).unwrap_err();
+        assert!(err.to_string().starts_with("resources/nope.txt"));
+
+        let args = ["lilgrep", "resources/tree", "tasi", "--include", "[z"].map(String::from);
+        let err = run(Config::build(args.into_iter()).unwrap()).unwrap_err();
+        assert!(matches!(err, LilgrepError::Glob { .. }));
+
+        let args = ["lilgrep", "-", "a", "--replace", "b", "--write"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert_eq!(
+            "'--write' can't be used with the standard input",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn skip_binary() {
+        let mut text = BufReader::new(File::open("resources/file.txt").unwrap());
+        assert!(!stream::is_binary(&mut text).unwrap());
+        let mut binary = BufReader::new(File::open("resources/tree/data.bin").unwrap());
+        assert!(stream::is_binary(&mut binary).unwrap());
+    }
+
+    // Searches a single file the way run does, into a string
+    fn search_file(args: &[&str]) -> String {
+        let config = Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+        let patterns = load_patterns(&config).unwrap();
+        let matcher = Matcher::with_options(&patterns, &config.matcher_options()).unwrap();
+        let searcher = config.searcher(matcher, false);
+        let (_, _, out) =
+            search_one(&config.file_path, &searcher, &config, false, Vec::new()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn encodings() {
+        let expected = "2:the café of Tasi 🦀\n";
+        for file in ["utf16le.txt", "utf16be.txt", "utf8-bom.txt"] {
+            let path = format!("resources/encodings/{file}");
+            assert_eq!(expected, search_file(&["lilgrep", &path, "café", "-n"]));
+        }
+        // The BOM isn't part of the first line
+        let path = "resources/encodings/utf8-bom.txt";
+        assert_eq!(
+            "first line\n",
+            search_file(&["lilgrep", path, "-E", "^first"])
+        );
+
+        let path = "resources/encodings/latin1.txt";
+        assert_eq!("", search_file(&["lilgrep", path, "café"]));
+        assert_eq!(
+            "the café of Tasi\n",
+            search_file(&["lilgrep", path, "café", "--encoding", "latin1"])
+        );
+        let args = ["lilgrep", path, "café", "--encoding", "ebcdic"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn compressed_files() {
+        for file in ["app.log.1.gz", "app.log.2.bz2", "app.log.3.zst"] {
+            let path = format!("resources/compressed/{file}");
+            assert_eq!(
+                "2:ERROR disk full\n4:ERROR disk still full\n",
+                search_file(&["lilgrep", &path, "ERROR", "-n", "-z"])
+            );
+            // Without -z they're only binary files, lines are never printed
+            let raw = search_file(&["lilgrep", &path, "ERROR"]);
+            assert!(!raw.contains("disk"));
+        }
+
+        let args = ["lilgrep", "resources/compressed", "full", "-z", "-c"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        let searcher = config.searcher(Matcher::literal("full"), true);
+        let files = walk::files("resources/compressed", &WalkOptions::default()).unwrap();
+        let mut out = Vec::new();
+        search_files(files, Arc::new(config), Arc::new(searcher), &mut out).unwrap();
+        let expected = "resources/compressed/app.log:2\n\
+                        resources/compressed/app.log.1.gz:2\n\
+                        resources/compressed/app.log.2.bz2:2\n\
+                        resources/compressed/app.log.3.zst:2\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap().replace('\\', "/"));
+    }
+
+    #[test]
+    fn skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("broken.gz"),
+            b"\x1f\x8b\x08 not really gzip",
+        )
+        .unwrap();
+        fs::write(dir.path().join("notes.txt"), "a line with tasi\n").unwrap();
+        let root = dir.path().to_str().unwrap();
+
+        // The other files are still searched and printed
+        let args = ["lilgrep", root, "tasi", "-z"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        let searcher = config.searcher(Matcher::literal("tasi"), true);
+        let files = walk::files(root, &WalkOptions::default()).unwrap();
+        let mut out = Vec::new();
+        let (found, _, skipped) =
+            search_files(files, Arc::new(config), Arc::new(searcher), &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("a line with tasi"));
+        // But the failure wins over what was found
+        let err = skipped.result(found).unwrap_err();
+        assert!(matches!(err, LilgrepError::Skipped { files: 1, .. }));
+        assert_eq!("1 file couldn't be searched", err.to_string());
+        assert!(err.exit_code() >= 2);
+
+        let args = ["lilgrep", root, "--query", "nothing", "-z"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        let query = Query::parse("nothing").unwrap();
+        let err = run_query(&config, &query, Vec::new()).unwrap_err();
+        assert!(matches!(err, LilgrepError::Skipped { files: 1, .. }));
+    }
+
+    #[test]
+    fn replace_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("one.txt"), "tasi\n").unwrap();
+        fs::write(dir.path().join("two.txt"), "tasi\ntasi\n").unwrap();
+        let root = dir.path().to_str().unwrap();
+        let args = ["lilgrep", root, "tasi", "--replace", "Tasi", "--write"];
+        let config = Config::build(args.into_iter().map(String::from)).unwrap();
+        let mut out = Vec::new();
+        let matcher = Matcher::literal("tasi");
+        assert!(run_replace(&config, &matcher, "Tasi", &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("one.txt: 1 line changed\n"));
+        assert!(out.contains("two.txt: 2 lines changed\n"));
+    }
+
+    #[test]
+    fn word_and_line_flags() {
+        let path = "resources/file.txt";
+        assert_eq!("", search_file(&["lilgrep", path, "-w", "Tas"]));
+        assert_eq!(
+            "my name is Tasi\n",
+            search_file(&["lilgrep", path, "-w", "Tasi"])
+        );
+        assert_eq!(
+            "my name is Tasi\n",
+            search_file(&["lilgrep", path, "-x", "my name is Tasi"])
+        );
+        assert_eq!("", search_file(&["lilgrep", path, "-x", "name is Tasi"]));
+        // -F after -E treats the pattern as plain text again
+        assert_eq!("", search_file(&["lilgrep", path, "-E", "-F", "T.si"]));
+        assert_eq!(
+            "my name is Tasi\n",
+            search_file(&["lilgrep", path, "-F", "-E", "-w", "T.si"])
+        );
+    }
+
+    #[test]
+    fn binary_files() {
+        let path = "resources/tree/data.bin";
+        assert_eq!(
+            "Binary file resources/tree/data.bin matches\n",
+            search_file(&["lilgrep", path, "tasi", "-n"])
+        );
+        assert_eq!("", search_file(&["lilgrep", path, "nothing"]));
+        assert_eq!("1\n", search_file(&["lilgrep", path, "tasi", "-c"]));
+        assert_eq!(
+            "tasi\0\u{1}\u{2}binary\n",
+            search_file(&["lilgrep", path, "tasi", "-a"])
+        );
+    }
+
+    #[test]
+    fn glob_flags() {
+        let args = [
+            "lilgrep",
+            "src/",
+            "tasi",
+            "--include",
+            "*.rs",
+            "--exclude",
+            "target/",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["*.rs"], config.walk.include);
+        assert_eq!(vec!["target/"], config.walk.exclude);
+        let args = ["lilgrep", "src/", "tasi", "--include"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn pattern_flags() {
+        let args = [
+            "lilgrep",
+            "-e",
+            "ERROR",
+            "file.txt",
+            "-e",
+            "WARN",
+            "-f",
+            "patterns.txt",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!("file.txt", config.file_path);
+        assert_eq!(vec!["ERROR", "WARN"], config.patterns);
+        assert_eq!(vec!["patterns.txt"], config.pattern_files);
+        // The query can't be there together with -e
+        let args = ["lilgrep", "-e", "ERROR", "file.txt", "tasi"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn pattern_file() {
+        let args = [
+            "lilgrep",
+            "resources/file.txt",
+            "-f",
+            "resources/patterns.txt",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["Tasi", "word"], load_patterns(&config).unwrap());
+        let args = [
+            "lilgrep",
+            "resources/file.txt",
+            "-f",
+            "resources/missing.txt",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(run(config).is_err());
+    }
+
+    #[test]
+    fn output_mode_flags() {
+        let args = ["lilgrep", "-v", "file.txt", "tasi", "-c", "-m", "3"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.invert);
+        assert_eq!(OutputMode::Count, config.mode);
+        assert_eq!(Some(3), config.search_options().max_count);
+        // The last of -c, -l and -L wins
+        let args = ["lilgrep", "-c", "-L", "file.txt", "tasi"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(OutputMode::FilesWithoutMatch, config.mode);
+        assert_eq!(Some(1), config.search_options().max_count);
+    }
+
+    #[test]
+    fn parallel_order() {
+        let args = ["lilgrep", "resources/tree", "tasi", "-j", "4", "-A", "1"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        let searcher = config.searcher(Matcher::literal("tasi"), true);
+        let files = walk::files("resources/tree", &WalkOptions::default()).unwrap();
+        let expected = "resources/tree/build/generated.rs:fn tasi_generated() {}\n\
+                        --\n\
+                        Binary file resources/tree/data.bin matches\n\
+                        --\n\
+                        resources/tree/notes.txt:a line with tasi\n\
+                        resources/tree/notes.txt-and another one\n\
+                        --\n\
+                        resources/tree/src/lib.rs:pub fn tasi() {}\n\
+                        --\n\
+                        resources/tree/src/main.rs:    tasi();\n\
+                        resources/tree/src/main.rs-}\n";
+        // Whatever thread ends first, the output is the same
+        let (config, searcher) = (Arc::new(config), Arc::new(searcher));
+        for _ in 0..10 {
+            let mut out = Vec::new();
+            let found = search_files(
+                files.clone(),
+                Arc::clone(&config),
+                Arc::clone(&searcher),
+                &mut out,
+            );
+            assert!(found.unwrap().0);
+            assert_eq!(expected, String::from_utf8(out).unwrap().replace('\\', "/"));
+        }
+    }
+
+    #[test]
+    fn color_flag() {
+        let args = ["lilgrep", "--color=always", "src", "tasi"].map(String::from);
+        assert!(Config::build(args.into_iter()).unwrap().color);
+        let args = ["lilgrep", "--color=never", "src", "tasi"].map(String::from);
+        assert!(!Config::build(args.into_iter()).unwrap().color);
+        // The tests output isn't a terminal
+        let args = ["lilgrep", "--color=auto", "src", "tasi"].map(String::from);
+        assert!(!Config::build(args.into_iter()).unwrap().color);
+        let args = ["lilgrep", "--color=sometimes", "src", "tasi"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn threads_flag() {
+        let args = ["lilgrep", "-j", "3", "src", "tasi"].map(String::from);
+        assert_eq!(3, Config::build(args.into_iter()).unwrap().threads);
+        let args = ["lilgrep", "-j", "0", "src", "tasi"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn replace_flags() {
+        let args = ["lilgrep", "src", "-E", "(a)", "--replace", "$1b", "--write"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(Some("$1b".to_string()), config.replace);
+        assert!(config.write);
+        let args = ["lilgrep", "src", "tasi", "--write"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn ignore_flags() {
+        let args = ["lilgrep", "src", "tasi"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(!config.walk.no_ignore && !config.walk.hidden);
+        let args = ["lilgrep", "--no-ignore", "src", "tasi", "--hidden"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.walk.no_ignore && config.walk.hidden);
+    }
+
+    #[test]
+    fn regex_flag() {
+        let args = ["lilgrep", "file.txt", "-E", r"error\d{3}"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.regex);
+        assert_eq!(vec![r"error\d{3}"], config.patterns);
+    }
+
+    #[test]
+    fn index_command() {
+        let command = |args: &[&str]| Command::build(args.iter().map(|arg| arg.to_string()));
+        let built = command(&["lilgrep", "index", "build", "src"]);
+        assert!(matches!(built, Ok(Command::BuildIndex(dir)) if dir == "src"));
+        let built = command(&["lilgrep", "index", "build", "-n", "src"]);
+        assert_eq!(
+            ArgError::UnexpectedArgument("-n".to_string()),
+            built.unwrap_err()
+        );
+        let built = command(&["lilgrep", "index", "build"]);
+        assert_eq!(ArgError::MissingArgument("DIR"), built.unwrap_err());
+        // Anything else is a search, even of a directory called index
+        let built = command(&["lilgrep", "index", "tasi", "--indexed"]);
+        assert!(matches!(built, Ok(Command::Search(config)) if config.indexed));
+    }
+
+    #[test]
+    fn follow_flag() {
+        let args = ["lilgrep", "--follow", "app.log", "ERROR"].map(String::from);
+        assert!(Config::build(args.into_iter()).unwrap().follow);
+        let args = ["lilgrep", "--follow", "-", "ERROR"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert_eq!(
+            ArgError::Incompatible("--follow", "the standard input"),
+            err
+        );
+        let args = ["lilgrep", "--follow", "-c", "app.log", "ERROR"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert_eq!(ArgError::Incompatible("--follow", "-c, -l or -L"), err);
+    }
+
+    #[test]
+    fn query_flags() {
+        let args = ["lilgrep", "src", "--query", "a AND b"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(Some("a AND b".to_string()), config.query);
+        assert!(config.patterns.is_empty());
+
+        let args = ["lilgrep", "src", "--query", "a", "--query-scope", "file"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(OutputMode::FilesWithMatches, config.mode);
+
+        let args = ["lilgrep", "src", "--query", "a", "-E"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert_eq!(ArgError::Incompatible("--query", "-E, -w or -x"), err);
+        let args = ["lilgrep", "src", "--query", "a", "--query-scope", "dir"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn fuzzy_flag() {
+        let args = ["lilgrep", "src", "tasi", "--fuzzy", "1"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(Some(1), config.matcher_options().fuzzy);
+        let args = ["lilgrep", "src", "tasi", "--fuzzy", "1", "-w"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert_eq!(ArgError::Incompatible("--fuzzy", "-E, -w, -x or -U"), err);
+        let args = ["lilgrep", "src", "tasi", "--fuzzy", "one"].map(String::from);
+        assert!(Config::build(args.into_iter()).is_err());
+
+        let content = "my name is Tasi\nmy nmae is tsai\nnothing";
+        let results = search_fuzzy(content, "name", 2).unwrap();
+        let found: Vec<(&str, usize)> = results
+            .iter()
+            .map(|result| (&result.line[result.span.clone()], result.distance))
+            .collect();
+        assert_eq!(vec![("name", 0), ("nmae", 2)], found);
+        assert!(search_fuzzy(content, "name", 4).is_err());
+    }
+
+    #[test]
+    fn type_flags() {
+        let args = ["lilgrep", "-t", "rust", "src", "tasi", "-T", "log"];
+        let with_log = args.iter().chain(&["--type-add", "log:*.log"]);
+        let config = Config::build(with_log.map(|arg| arg.to_string())).unwrap();
+        assert_eq!(vec!["rust"], config.walk.types);
+        assert_eq!(vec!["log"], config.walk.types_not);
+        // log is only known with --type-add
+        let err = Config::build(args.into_iter().map(String::from)).unwrap_err();
+        assert!(matches!(err, ArgError::InvalidValue { option, .. } if option == "-T"));
+        let args = ["lilgrep", "--type-list"].map(String::from);
+        let err = Config::build(args.into_iter()).unwrap_err();
+        assert!(matches!(err, ArgError::TypeList(list) if list.contains("rust: *.rs\n")));
     }
 }
@@ -1,20 +1,35 @@
-use lilgrep::Config;
+use lilgrep::{ArgError, Command, LilgrepError};
 use std::{env, process};
 
+// The exit status follows grep: 0 when something was found,
+// 1 when nothing was found and 2 or more when something went wrong.
+// Each kind of error has its own status, see LilgrepError::exit_code
 fn main() {
     // Get the arguments into config struct type
-    let config = Config::build(env::args()).unwrap_or_else(|err| {
-        // The eprintln! macro sends the error to the standard error stream
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
+    let command = Command::build(env::args()).unwrap_or_else(|err| match err {
+        // Asking for help isn't a failure, the text goes to the standard output
+        ArgError::Help(text) | ArgError::Version(text) | ArgError::TypeList(text) => {
+            print!("{text}");
+            process::exit(0);
+        }
+        err => {
+            // The eprintln! macro sends the error to the standard error stream
+            eprintln!("Problem parsing arguments: {}", err);
+            eprintln!("Try 'lilgrep --help' for more information");
+            process::exit(LilgrepError::from(err).exit_code());
+        }
     });
 
-    // The unwrap_or_else doesn't fit here because no Ok value
-    // is being expected, just an error that may occur
-    if let Err(e) = lilgrep::run(config) {
-        eprintln!("Application run error: {}", e);
-        process::exit(1);
+    let result = match command {
+        Command::Search(config) => lilgrep::run(*config),
+        Command::BuildIndex(dir) => lilgrep::build_index(&dir),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Application run error: {}", e);
+            process::exit(e.exit_code());
+        }
     }
-
-    
 }
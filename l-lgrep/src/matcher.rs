@@ -0,0 +1,693 @@
+use crate::{fuzzy::Fuzzy, LilgrepError};
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder};
+use std::{borrow::Cow, ops::Range};
+
+// A Matcher knows how to find the query inside a single line.
+// The query is prepared once, when the Matcher is built, and then
+// reused for every line, no matter where the lines come from
+#[derive(Debug)]
+pub struct Matcher {
+    kind: Kind,
+}
+
+// How a Matcher looks for its patterns. It stays private, so a new way
+// of matching doesn't change what the library offers
+#[derive(Debug)]
+enum Kind {
+    // Plain substring search, the default
+    Literal(String),
+    // Substring search ignoring case, the query is kept already folded
+    CaseInsensitive(String),
+    // Many plain queries at once. The automaton walks the line a single
+    // time no matter how many patterns there are. With ignore_case the
+    // patterns were folded before building it
+    Literals {
+        automaton: AhoCorasick,
+        ignore_case: bool,
+    },
+    // Regular expression search, selected by -E
+    Regex(Regex),
+    // Fixed strings with -i and -w or -x. The regex engine folds case a
+    // char at a time, so "straße" wouldn't find "STRASSE" there: each
+    // pattern has its own CaseInsensitive matcher, and the word or line
+    // bounds are checked around its matches in the original line
+    Bounded {
+        patterns: Vec<Matcher>,
+        whole_line: bool,
+    },
+    // Approximate search, selected by --fuzzy, one per pattern
+    Fuzzy(Vec<Fuzzy>),
+}
+
+// How the patterns are turned into a Matcher
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MatcherOptions {
+    // -E, the patterns are regular expressions. Without it, or with -F,
+    // they're fixed strings and regex metacharacters mean themselves
+    pub regex: bool,
+    // -i
+    pub ignore_case: bool,
+    // -w, a match can't have word chars right before or after it
+    pub word: bool,
+    // -x, a match must be the whole line
+    pub whole_line: bool,
+    // -U, the matcher sees many lines at once, so ^ and $ must anchor at
+    // every line instead of only at the ends of the input
+    pub multiline: bool,
+    // --fuzzy, how many edits a match can be away from a pattern
+    pub fuzzy: Option<usize>,
+}
+
+impl Matcher {
+    // A line matches if any of the patterns matches.
+    // Building can fail because a regular expression might be invalid
+    pub fn new(
+        patterns: &[String],
+        regex: bool,
+        ignore_case: bool,
+    ) -> Result<Matcher, LilgrepError> {
+        let options = MatcherOptions {
+            regex,
+            ignore_case,
+            ..MatcherOptions::default()
+        };
+        Matcher::with_options(patterns, &options)
+    }
+
+    // Same as new, with every option. Words and whole lines are found by
+    // the regex engine: fixed strings are escaped into a regular
+    // expression and then anchored like any other. With -i they're the
+    // exception, see Kind::Bounded
+    pub fn with_options(
+        patterns: &[String],
+        options: &MatcherOptions,
+    ) -> Result<Matcher, LilgrepError> {
+        let ignore_case = options.ignore_case;
+        if let Some(max) = options.fuzzy {
+            let fuzzies = patterns
+                .iter()
+                .map(|pattern| {
+                    Fuzzy::new(pattern, max, ignore_case).map_err(|err| LilgrepError::Pattern {
+                        pattern: pattern.clone(),
+                        source: err.into(),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(Matcher {
+                kind: Kind::Fuzzy(fuzzies),
+            });
+        }
+        let anchored = options.word || options.whole_line;
+        if anchored && ignore_case && !options.regex {
+            return Ok(Matcher {
+                kind: Kind::Bounded {
+                    patterns: patterns
+                        .iter()
+                        .map(|pattern| Matcher::case_insensitive(pattern))
+                        .collect(),
+                    whole_line: options.whole_line,
+                },
+            });
+        }
+        // Without any pattern, like with an empty -f file, nothing matches.
+        // An alternation of no branches would be the empty regular
+        // expression, that matches everything, so it's left to the
+        // automaton below, which has nothing to find
+        if (options.regex || anchored) && !patterns.is_empty() {
+            let escaped: Vec<String>;
+            let patterns = if options.regex {
+                patterns
+            } else {
+                escaped = patterns
+                    .iter()
+                    .map(|pattern| regex::escape(pattern))
+                    .collect();
+                &escaped
+            };
+            // Many regular expressions become a single one, each
+            // pattern being a branch of the alternation
+            let pattern = match patterns {
+                [single] if !anchored => single.clone(),
+                _ => patterns
+                    .iter()
+                    .map(|pattern| format!("(?:{pattern})"))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            };
+            // -x wins over -w, like in grep. The half word boundaries
+            // only look outside the match, so "-w foo!" still works: the
+            // char before the match and the one after it must not be
+            // Unicode word chars, whatever the match itself starts and
+            // ends with
+            let pattern = if options.whole_line {
+                format!("^(?:{pattern})$")
+            } else if options.word {
+                format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}")
+            } else {
+                pattern
+            };
+            // The regex engine already knows how to fold Unicode case.
+            // With crlf a line ending in \r\n still ends right before \r
+            let regex = RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .multi_line(options.multiline)
+                .crlf(options.multiline)
+                .build()
+                .map_err(|err| LilgrepError::Pattern {
+                    pattern: pattern.clone(),
+                    source: err.into(),
+                })?;
+            return Ok(Matcher {
+                kind: Kind::Regex(regex),
+            });
+        }
+        match patterns {
+            [single] if ignore_case => Ok(Matcher::case_insensitive(single)),
+            [single] => Ok(Matcher::literal(single)),
+            _ => {
+                let folded: Vec<String>;
+                let patterns = if ignore_case {
+                    folded = patterns.iter().map(|pattern| fold_case(pattern)).collect();
+                    &folded
+                } else {
+                    patterns
+                };
+                // LeftmostFirst reports the match that starts first in the
+                // line, the same one a regular expression would find
+                let automaton = AhoCorasick::builder()
+                    .match_kind(MatchKind::LeftmostFirst)
+                    .build(patterns)
+                    .map_err(|err| LilgrepError::Pattern {
+                        pattern: patterns.join(", "),
+                        source: err.into(),
+                    })?;
+                Ok(Matcher {
+                    kind: Kind::Literals {
+                        automaton,
+                        ignore_case,
+                    },
+                })
+            }
+        }
+    }
+
+    // Approximate search of every pattern, up to max edits away. It
+    // fails only for a pattern too long for the bit-parallel search
+    pub fn fuzzy(
+        patterns: &[String],
+        max: usize,
+        ignore_case: bool,
+    ) -> Result<Matcher, LilgrepError> {
+        let options = MatcherOptions {
+            ignore_case,
+            fuzzy: Some(max),
+            ..MatcherOptions::default()
+        };
+        Matcher::with_options(patterns, &options)
+    }
+
+    // A single fixed string, with its case. Unlike new, this one and
+    // case_insensitive can't fail
+    pub fn literal(query: &str) -> Matcher {
+        Matcher {
+            kind: Kind::Literal(query.to_string()),
+        }
+    }
+
+    pub fn case_insensitive(query: &str) -> Matcher {
+        Matcher {
+            kind: Kind::CaseInsensitive(fold_case(query)),
+        }
+    }
+
+    // True when the patterns became a regular expression, even fixed
+    // strings do with -w or -x
+    pub(crate) fn is_regex(&self) -> bool {
+        matches!(self.kind, Kind::Regex(_))
+    }
+
+    // True for the matchers that look for plain text, with its case
+    pub fn is_literal(&self) -> bool {
+        matches!(
+            self.kind,
+            Kind::Literal(_)
+                | Kind::Literals {
+                    ignore_case: false,
+                    ..
+                }
+        )
+    }
+
+    // Where the first match is inside the line, if there's any
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        match &self.kind {
+            Kind::Literal(query) => line
+                .find(query.as_str())
+                .map(|start| start..start + query.len()),
+            Kind::CaseInsensitive(query) => find_folded(line, |folded| {
+                folded
+                    .find(query.as_str())
+                    .map(|start| start..start + query.len())
+            }),
+            Kind::Literals {
+                automaton,
+                ignore_case: false,
+            } => automaton.find(line).map(|found| found.range()),
+            Kind::Literals {
+                automaton,
+                ignore_case: true,
+            } => find_folded(line, |folded| {
+                automaton.find(folded).map(|found| found.range())
+            }),
+            Kind::Regex(regex) => regex.find(line).map(|found| found.range()),
+            Kind::Bounded {
+                patterns,
+                whole_line,
+            } => find_bounded(patterns, *whole_line, line).into_iter().next(),
+            Kind::Fuzzy(fuzzies) => find_fuzzy(fuzzies, line)
+                .into_iter()
+                .next()
+                .map(|(span, _)| span),
+        }
+    }
+
+    // The line with every match replaced by the template. Only regular
+    // expressions expand $1 or ${name} to the captured groups, for the
+    // other matchers the template is plain text
+    pub fn replace_all<'a>(&self, line: &'a str, template: &str) -> Cow<'a, str> {
+        if let Kind::Regex(regex) = &self.kind {
+            return regex.replace_all(line, template);
+        }
+        let spans = self.find_all(line);
+        if spans.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        let mut replaced = String::with_capacity(line.len());
+        let mut last = 0;
+        for span in spans {
+            replaced.push_str(&line[last..span.start]);
+            replaced.push_str(template);
+            last = span.end;
+        }
+        replaced.push_str(&line[last..]);
+        Cow::Owned(replaced)
+    }
+
+    // Every match inside the line, in order and without overlapping.
+    // Empty matches are left out, there's nothing to show for them
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut spans = self.find_every(line);
+        spans.retain(|span| !span.is_empty());
+        spans
+    }
+
+    // Same as find_all, but keeping the empty matches. The -U search
+    // needs them: an empty match still selects its line
+    pub fn find_every(&self, line: &str) -> Vec<Range<usize>> {
+        match &self.kind {
+            Kind::Literal(query) if query.is_empty() => (0..=line.len())
+                .filter(|at| line.is_char_boundary(*at))
+                .map(|at| at..at)
+                .collect(),
+            Kind::Literal(query) => line
+                .match_indices(query.as_str())
+                .map(|(start, found)| start..start + found.len())
+                .collect(),
+            Kind::CaseInsensitive(query) if query.is_empty() => {
+                Matcher::literal("").find_every(line)
+            }
+            Kind::CaseInsensitive(query) => find_all_folded(line, |folded| {
+                folded
+                    .match_indices(query.as_str())
+                    .map(|(start, found)| start..start + found.len())
+                    .collect()
+            }),
+            Kind::Literals {
+                automaton,
+                ignore_case: false,
+            } => automaton
+                .find_iter(line)
+                .map(|found| found.range())
+                .collect(),
+            Kind::Literals {
+                automaton,
+                ignore_case: true,
+            } => find_all_folded(line, |folded| {
+                automaton
+                    .find_iter(folded)
+                    .map(|found| found.range())
+                    .collect()
+            }),
+            Kind::Regex(regex) => regex.find_iter(line).map(|found| found.range()).collect(),
+            Kind::Bounded {
+                patterns,
+                whole_line,
+            } => find_bounded(patterns, *whole_line, line),
+            Kind::Fuzzy(fuzzies) => find_fuzzy(fuzzies, line)
+                .into_iter()
+                .map(|(span, _)| span)
+                .collect(),
+        }
+    }
+
+    // Same as find_all, along with how many edits away from its pattern
+    // each match is. Only --fuzzy has distances, the other matchers give
+    // none back
+    pub fn find_scored(&self, line: &str) -> (Vec<Range<usize>>, Vec<usize>) {
+        match &self.kind {
+            Kind::Fuzzy(fuzzies) => find_fuzzy(fuzzies, line).into_iter().unzip(),
+            _ => (self.find_all(line), Vec::new()),
+        }
+    }
+}
+
+// The matches of Kind::Bounded, the same ones the regular expression
+// of -w or -x would find. A match out of bounds doesn't hide the ones
+// overlapping it: the next one is looked for right after its first char.
+// When many patterns match at the same place the first one wins, like
+// the first branch of an alternation
+fn find_bounded(patterns: &[Matcher], whole_line: bool, line: &str) -> Vec<Range<usize>> {
+    let mut found: Vec<(usize, usize, Range<usize>)> = Vec::new();
+    for (index, pattern) in patterns.iter().enumerate() {
+        let mut at = 0;
+        while at <= line.len() {
+            let Some(span) = pattern.find(&line[at..]) else {
+                break;
+            };
+            let span = at + span.start..at + span.end;
+            let after_start = line[span.start..]
+                .chars()
+                .next()
+                .map_or(line.len() + 1, |c| span.start + c.len_utf8());
+            if in_bounds(line, &span, whole_line) {
+                at = span.end.max(after_start);
+                found.push((span.start, index, span));
+            } else {
+                at = after_start;
+            }
+        }
+    }
+    found.sort_by_key(|(start, index, _)| (*start, *index));
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for (_, _, span) in found {
+        if spans.last().is_none_or(|last| last.end <= span.start) {
+            spans.push(span);
+        }
+    }
+    spans
+}
+
+// -x: the span starts and ends a line. -w: there are no word chars right
+// before or after it. Only the chars outside the span count, like the
+// half word boundaries of the regular expression
+fn in_bounds(line: &str, span: &Range<usize>, whole_line: bool) -> bool {
+    let (before, after) = (&line[..span.start], &line[span.end..]);
+    if whole_line {
+        let starts = before.is_empty() || before.ends_with('\n');
+        let ends = after.is_empty() || after.starts_with('\n') || after.starts_with("\r\n");
+        return starts && ends;
+    }
+    // The same word chars as \w and \b of the regex engine
+    let word = regex_syntax::is_word_character;
+    !before.chars().next_back().is_some_and(word) && !after.chars().next().is_some_and(word)
+}
+
+// The matches of every fuzzy pattern together. Where two of them
+// overlap, the one starting first wins, and the closest one when they
+// start at the same place
+fn find_fuzzy(fuzzies: &[Fuzzy], line: &str) -> Vec<(Range<usize>, usize)> {
+    let mut found: Vec<(Range<usize>, usize)> = fuzzies
+        .iter()
+        .flat_map(|fuzzy| fuzzy.find_all(line))
+        .collect();
+    if fuzzies.len() > 1 {
+        found.sort_by_key(|(span, distance)| (span.start, *distance));
+        let mut last_end = 0;
+        found.retain(|(span, _)| {
+            let keep = span.start >= last_end;
+            if keep {
+                last_end = span.end;
+            }
+            keep
+        });
+    }
+    found
+}
+
+// Same as find_folded, but for every match in the line
+fn find_all_folded(
+    line: &str,
+    find_all: impl FnOnce(&str) -> Vec<Range<usize>>,
+) -> Vec<Range<usize>> {
+    let (folded, origins) = fold_case_with_origins(line);
+    find_all(&folded)
+        .into_iter()
+        .filter(|span| !span.is_empty())
+        .map(|span| {
+            let last = origins[span.end - 1];
+            let last_len = line[last..].chars().next().map_or(0, char::len_utf8);
+            origins[span.start]..last + last_len
+        })
+        .collect()
+}
+
+// Runs find over the folded line. The folded line may have a different
+// length than the original one, so the span found in it has to be taken
+// back to the original line
+fn find_folded(
+    line: &str,
+    find: impl FnOnce(&str) -> Option<Range<usize>>,
+) -> Option<Range<usize>> {
+    let (folded, origins) = fold_case_with_origins(line);
+    let span = find(&folded)?;
+    if span.is_empty() {
+        let start = origins.get(span.start).copied().unwrap_or(line.len());
+        return Some(start..start);
+    }
+    let last = origins[span.end - 1];
+    let last_len = line[last..].chars().next().map_or(0, char::len_utf8);
+    Some(origins[span.start]..last + last_len)
+}
+
+// str::to_lowercase alone isn't enough: 'ß' has no single char uppercase
+// partner and the final sigma 'ς' lowercases differently than 'σ'.
+// Lowercasing, then going through uppercase and back to lowercase puts
+// every case variant of a letter in the same form, even 'ẞ', 'ß' and
+// "ss", or 'K', the Kelvin sign, and 'k'. The index folds its text with
+// it too, so a match in the text is still a match in the folded text
+pub(crate) fn fold_case(text: &str) -> String {
+    text.chars().flat_map(fold_char).collect()
+}
+
+fn fold_char(c: char) -> impl Iterator<Item = char> {
+    c.to_lowercase()
+        .flat_map(char::to_uppercase)
+        .flat_map(char::to_lowercase)
+}
+
+// Same as fold_case, but also returns, for each byte of the folded text,
+// where the char that produced it starts in the original text
+fn fold_case_with_origins(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len());
+    for (origin, c) in text.char_indices() {
+        for folded_char in fold_char(c) {
+            folded.push(folded_char);
+            origins.resize(folded.len(), origin);
+        }
+    }
+    (folded, origins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn many_literals() {
+        let matcher = Matcher::new(&patterns(&["ERROR", "WARN", "panic"]), false, false).unwrap();
+        assert!(matches!(matcher.kind, Kind::Literals { .. }));
+        assert_eq!(Some(5..9), matcher.find("some WARN then ERROR"));
+        assert_eq!(None, matcher.find("all good, error free"));
+    }
+
+    #[test]
+    fn many_literals_ignoring_case() {
+        let matcher = Matcher::new(&patterns(&["straße", "error"]), false, true).unwrap();
+        let line = "Die STRASSE hat ERROR";
+        assert_eq!(Some(4..11), matcher.find(line));
+        assert_eq!(None, matcher.find("nothing"));
+    }
+
+    #[test]
+    fn capital_sharp_s() {
+        assert_eq!(fold_case("GROẞE"), fold_case("große"));
+        assert_eq!(fold_case("GROSSE"), fold_case("große"));
+        for (query, line) in [("große", "GROẞE"), ("GROẞE", "große"), ("GROẞE", "Grosse")] {
+            let matcher = Matcher::case_insensitive(query);
+            assert!(matcher.find(line).is_some(), "{query} in {line}");
+        }
+    }
+
+    #[test]
+    fn every_match() {
+        let matcher = Matcher::new(&patterns(&["ab"]), false, false).unwrap();
+        assert_eq!(vec![0..2, 4..6], matcher.find_all("abc ab"));
+        let matcher = Matcher::new(&patterns(&["SS"]), false, true).unwrap();
+        assert_eq!(vec![4..6, 10..12], matcher.find_all("Straße Pass"));
+        let matcher = Matcher::new(&patterns(&[r"\d+", "x*"]), true, false).unwrap();
+        assert_eq!(vec![1..3, 4..5], matcher.find_all("a12b3"));
+    }
+
+    #[test]
+    fn replace_with_captures() {
+        let matcher = Matcher::new(&patterns(&[r"(\w+)@(?<host>\w+)"]), true, false).unwrap();
+        assert_eq!(
+            "mail one:a - two:b",
+            matcher.replace_all("mail a@one - b@two", "${host}:$1")
+        );
+        let matcher = Matcher::new(&patterns(&["$1"]), false, true).unwrap();
+        assert_eq!("a $2 b $2", matcher.replace_all("a $1 b $1", "$2"));
+    }
+
+    #[test]
+    fn anchors_in_multiline() {
+        let content = "fn a(\n}\nfn b(\r\n}\n";
+        let multiline = MatcherOptions {
+            regex: true,
+            multiline: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&[r"^fn \w+\($"]), &multiline).unwrap();
+        assert_eq!(vec![0..5, 8..13], matcher.find_all(content));
+        let line = MatcherOptions {
+            whole_line: true,
+            multiline: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&["fn b("]), &line).unwrap();
+        assert_eq!(Some(8..13), matcher.find(content));
+    }
+
+    #[test]
+    fn no_patterns() {
+        for regex in [false, true] {
+            let matcher = Matcher::new(&[], regex, false).unwrap();
+            assert_eq!(None, matcher.find("anything"));
+            assert_eq!(None, matcher.find(""));
+        }
+        let word = MatcherOptions {
+            word: true,
+            ..MatcherOptions::default()
+        };
+        assert_eq!(None, Matcher::with_options(&[], &word).unwrap().find("a b"));
+    }
+
+    #[test]
+    fn many_regexes() {
+        let matcher = Matcher::new(&patterns(&[r"^fn \w+", r"error\d{3}"]), true, false).unwrap();
+        assert_eq!(Some(4..12), matcher.find("got error404"));
+        assert_eq!(Some(0..7), matcher.find("fn main() {"));
+        assert_eq!(None, matcher.find("error 404"));
+    }
+
+    #[test]
+    fn words_and_lines() {
+        let word = MatcherOptions {
+            word: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&["tasi"]), &word).unwrap();
+        assert_eq!(None, matcher.find("tasipasin"));
+        assert_eq!(Some(10..14), matcher.find("tasipasin tasi."));
+        // Unicode word chars count as word chars too
+        assert_eq!(None, matcher.find("tasié"));
+        assert_eq!(vec![0..4, 11..15], matcher.find_all("tasi tasi_ tasi"));
+        // Patterns ending with a non word char still match
+        let matcher = Matcher::with_options(&patterns(&["a.b", "c+"]), &word).unwrap();
+        assert_eq!(Some(5..8), matcher.find("xa.b a.b"));
+        assert_eq!(Some(0..2), matcher.find("c+ d"));
+
+        let line = MatcherOptions {
+            whole_line: true,
+            ignore_case: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&["ab", "abc"]), &line).unwrap();
+        assert_eq!(Some(0..3), matcher.find("ABC"));
+        assert_eq!(None, matcher.find("abcd"));
+        let line = MatcherOptions {
+            whole_line: true,
+            regex: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&[r"\d+"]), &line).unwrap();
+        assert_eq!(Some(0..3), matcher.find("123"));
+        assert_eq!(None, matcher.find("123 4"));
+    }
+
+    #[test]
+    fn words_and_lines_ignoring_case() {
+        // Folded like -i alone, "ß" still finds "SS"
+        let word = MatcherOptions {
+            word: true,
+            ignore_case: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&["straße"]), &word).unwrap();
+        assert_eq!(Some(4..11), matcher.find("Die STRASSE."));
+        assert_eq!(None, matcher.find("STRASSEN"));
+        assert_eq!(vec![9..16], matcher.find_all("STRASSEN STRASSE"));
+        // A match out of bounds doesn't hide the ones overlapping it
+        let matcher = Matcher::with_options(&patterns(&["a b", "b c"]), &word).unwrap();
+        assert_eq!(vec![3..6], matcher.find_all("xa b c"));
+        let matcher = Matcher::with_options(&patterns(&["ab", "abc"]), &word).unwrap();
+        assert_eq!(Some(0..3), matcher.find("ABC d"));
+        // Word chars are the ones of the regex engine, with or without -i:
+        // a combining accent and the undertie connector are word chars
+        let case_sensitive = MatcherOptions {
+            ignore_case: false,
+            ..word
+        };
+        for (pattern, line) in [("cafe", "cafe\u{301}"), ("foo", "foo\u{203f}bar")] {
+            for options in [word, case_sensitive] {
+                let matcher = Matcher::with_options(&patterns(&[pattern]), &options).unwrap();
+                assert_eq!(None, matcher.find(line), "{pattern} in {line}");
+            }
+        }
+
+        let line = MatcherOptions {
+            whole_line: true,
+            ignore_case: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::with_options(&patterns(&["die straße"]), &line).unwrap();
+        assert_eq!(Some(0..11), matcher.find("Die Strasse"));
+        assert_eq!(None, matcher.find("Die Strasse 1"));
+        assert_eq!(Some(4..15), matcher.find("foo\nDie Strasse\r\n"));
+    }
+
+    #[test]
+    fn fuzzy_patterns() {
+        let matcher = Matcher::fuzzy(&patterns(&["tasi", "pasin"]), 1, false).unwrap();
+        // "pasi" is one edit away from tasi too, pasin is closer
+        let line = "a pasin and tazi";
+        assert_eq!(Some(2..7), matcher.find(line));
+        assert_eq!((vec![2..7, 12..16], vec![0, 1]), matcher.find_scored(line));
+        assert_eq!(None, matcher.find("nothing"));
+        // Exact matchers have no distances
+        let matcher = Matcher::new(&patterns(&["tasi"]), false, false).unwrap();
+        assert_eq!(
+            (vec![2..6, 9..13], vec![]),
+            matcher.find_scored("a tasi b tasi")
+        );
+
+        let err = Matcher::fuzzy(&patterns(&["tasi"]), 4, false).unwrap_err();
+        assert_eq!(3, err.exit_code());
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+// The same worker pool of the web server: a fixed number of threads
+// waiting for jobs that come through a channel
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+        let (sender, receiver) = mpsc::channel();
+        // Arc can be shared over threads
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| Worker::new(Arc::clone(&receiver)))
+            .collect();
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    // Closing the channel makes every worker leave its loop once the
+    // jobs already sent are done
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::spawn(move || loop {
+            // The lock is released at the end of this statement, so other
+            // workers can take jobs while this one is busy
+            let message = receiver.lock().unwrap().recv();
+            match message {
+                Ok(job) => job(),
+                Err(_) => break,
+            }
+        });
+
+        Worker {
+            thread: Some(thread),
+        }
+    }
+}
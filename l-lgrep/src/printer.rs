@@ -0,0 +1,441 @@
+use crate::{json, Config, Sink, SinkLine};
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    ops::Range,
+};
+
+// The same colors grep uses by default
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const PATH_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+// When the output gets ANSI colors, chosen with --color
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+    // Only when the standard output is a terminal and NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // Decides once, for the whole run, if the colors are on
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org: any non empty value turns colors off
+            ColorChoice::Auto => {
+                io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+// The "--" line between groups of context lines
+pub fn group_separator(color: bool) -> String {
+    paint(color, SEPARATOR_COLOR, "--")
+}
+
+fn paint(color: bool, code: &str, text: &str) -> String {
+    if color {
+        format!("{code}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
+
+// What is printed for each file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputMode {
+    // The selected lines, the default
+    #[default]
+    Lines,
+    // -c, how many lines were selected
+    Count,
+    // -l, only the path of files with at least one selected line
+    FilesWithMatches,
+    // -L, only the path of files without any selected line
+    FilesWithoutMatch,
+    // --json, one JSON object per event, see the json module
+    Json,
+}
+
+// Totals of a search, printed at the end of the JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Stats {
+    pub files_searched: usize,
+    pub files_with_matches: usize,
+    pub matched_lines: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: Stats) {
+        self.files_searched += other.files_searched;
+        self.files_with_matches += other.files_with_matches;
+        self.matched_lines += other.matched_lines;
+    }
+}
+
+// Writes the totals, only the JSON output has them
+pub fn write_summary<W: Write>(out: &mut W, mode: OutputMode, stats: &Stats) -> io::Result<()> {
+    if mode == OutputMode::Json {
+        writeln!(out, "{}", json::summary(stats))?;
+    }
+    Ok(())
+}
+
+// Prints matching lines and their context the way grep does.
+// Matching lines use ':' after the prefixes and context lines use '-',
+// groups of lines that aren't next to each other are split by "--"
+#[derive(Debug)]
+pub struct Printer<W: Write> {
+    // Where the lines go, the standard output or a buffer in the tests
+    out: W,
+    mode: OutputMode,
+    // Prefix every line with the file path
+    with_path: bool,
+    // -n
+    line_number: bool,
+    // -b
+    byte_offset: bool,
+    // True when -A, -B or -C were given, the "--" separators are only
+    // printed in that case
+    context: bool,
+    // Paint matches, paths and numbers with ANSI colors
+    color: bool,
+    path: String,
+    // Line number of the last line printed for the current file
+    last_line: Option<usize>,
+    // Whether any line was printed at all, across every file
+    printed: bool,
+    // Whether the JSON begin event was already printed for this file
+    begun: bool,
+    // The current file is binary, its lines are counted but not printed
+    binary: bool,
+    stats: Stats,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(config: &Config, with_path: bool, out: W) -> Printer<W> {
+        Printer {
+            out,
+            mode: config.mode,
+            with_path,
+            line_number: config.line_number,
+            byte_offset: config.byte_offset,
+            context: config.before_context > 0 || config.after_context > 0,
+            color: config.color,
+            path: String::new(),
+            last_line: None,
+            printed: false,
+            begun: false,
+            binary: false,
+            stats: Stats::default(),
+        }
+    }
+
+    // Must be called before printing lines of a new file
+    pub fn begin_file(&mut self, path: &str) {
+        self.path = path.to_string();
+        self.last_line = None;
+        self.begun = false;
+        self.binary = false;
+    }
+
+    // Must be called after the last line of a file, with how many lines
+    // were selected in it. Returns if the file counts as a success: it
+    // had a match, or it had none and only those are listed
+    pub fn end_file(&mut self, count: usize) -> io::Result<bool> {
+        self.stats.files_searched += 1;
+        self.stats.matched_lines += count;
+        if count > 0 {
+            self.stats.files_with_matches += 1;
+        }
+        match self.mode {
+            OutputMode::Lines if self.binary && count > 0 => {
+                writeln!(self.out, "Binary file {} matches", self.path)?
+            }
+            OutputMode::Lines => {}
+            // Files without any printed line don't have events at all
+            OutputMode::Json if self.begun => {
+                writeln!(self.out, "{}", json::end(&self.path, count))?
+            }
+            OutputMode::Json => {}
+            OutputMode::Count if self.with_path => {
+                let path = paint(self.color, PATH_COLOR, &self.path);
+                let separator = paint(self.color, SEPARATOR_COLOR, ":");
+                writeln!(self.out, "{path}{separator}{count}")?
+            }
+            OutputMode::Count => writeln!(self.out, "{count}")?,
+            OutputMode::FilesWithMatches if count > 0 => {
+                writeln!(self.out, "{}", paint(self.color, PATH_COLOR, &self.path))?
+            }
+            OutputMode::FilesWithoutMatch if count == 0 => {
+                writeln!(self.out, "{}", paint(self.color, PATH_COLOR, &self.path))?
+            }
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {}
+        }
+        if self.mode == OutputMode::FilesWithoutMatch {
+            Ok(count == 0)
+        } else {
+            Ok(count > 0)
+        }
+    }
+
+    // Totals of every file ended by this printer
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Gives back the writer, so the tests can look at what was printed
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn print(
+        &mut self,
+        separator: char,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+        spans: &[Range<usize>],
+        distances: &[usize],
+    ) -> io::Result<()> {
+        if self.binary {
+            return Ok(());
+        }
+        if self.mode == OutputMode::Json {
+            return self.print_json(separator, line_number, byte_offset, line, spans, distances);
+        }
+        // Only the lines mode prints the lines themselves
+        if self.mode != OutputMode::Lines {
+            return Ok(());
+        }
+        if self.context && self.printed {
+            // A gap between the last printed line and this one, or a new
+            // file, starts a new group
+            let adjacent = self.last_line.is_some_and(|last| last + 1 == line_number);
+            if !adjacent {
+                writeln!(self.out, "{}", group_separator(self.color))?;
+            }
+        }
+        self.last_line = Some(line_number);
+        self.printed = true;
+
+        let separator = paint(self.color, SEPARATOR_COLOR, &separator.to_string());
+        let mut prefix = String::new();
+        if self.with_path {
+            prefix.push_str(&paint(self.color, PATH_COLOR, &self.path));
+            prefix.push_str(&separator);
+        }
+        if self.line_number {
+            prefix.push_str(&paint(self.color, NUMBER_COLOR, &line_number.to_string()));
+            prefix.push_str(&separator);
+        }
+        if self.byte_offset {
+            prefix.push_str(&paint(self.color, NUMBER_COLOR, &byte_offset.to_string()));
+            prefix.push_str(&separator);
+        }
+        writeln!(self.out, "{prefix}{}", self.highlight(line, spans))
+    }
+
+    fn print_json(
+        &mut self,
+        separator: char,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+        spans: &[Range<usize>],
+        distances: &[usize],
+    ) -> io::Result<()> {
+        if !self.begun {
+            writeln!(self.out, "{}", json::begin(&self.path))?;
+            self.begun = true;
+        }
+        let event = if separator == ':' {
+            json::matched(&self.path, line_number, byte_offset, line, spans, distances)
+        } else {
+            json::context(&self.path, line_number, byte_offset, line)
+        };
+        writeln!(self.out, "{event}")
+    }
+
+    fn highlight(&self, line: &str, spans: &[Range<usize>]) -> String {
+        if !self.color || spans.is_empty() {
+            return line.to_string();
+        }
+        let mut painted = String::with_capacity(line.len() + spans.len() * 16);
+        let mut last = 0;
+        for span in spans {
+            painted.push_str(&line[last..span.start]);
+            painted.push_str(&paint(true, MATCH_COLOR, &line[span.clone()]));
+            last = span.end;
+        }
+        painted.push_str(&line[last..]);
+        painted
+    }
+}
+
+// The search sends its lines here, the spans of a match are the parts of
+// the line to highlight
+impl<W: Write> Sink for Printer<W> {
+    fn matched(&mut self, line: &SinkLine) -> io::Result<bool> {
+        self.print(
+            ':',
+            line.line_number,
+            line.byte_offset,
+            line.line,
+            line.spans,
+            line.distances,
+        )?;
+        // Only -c needs more than the first match of a binary file
+        Ok(!self.binary || self.mode == OutputMode::Count)
+    }
+
+    fn context(&mut self, line: &SinkLine) -> io::Result<()> {
+        self.print('-', line.line_number, line.byte_offset, line.line, &[], &[])
+    }
+
+    // The lines of a binary file are counted but never printed, end_file
+    // says if it matched
+    fn binary(&mut self) -> io::Result<()> {
+        self.binary = true;
+        Ok(())
+    }
+
+    // Tells the search if it's worth finding every match of the line
+    fn wants_spans(&self) -> bool {
+        (self.color && self.mode == OutputMode::Lines) || self.mode == OutputMode::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(args: &[&str]) -> Printer<Vec<u8>> {
+        let mut all = vec!["lilgrep", "file.txt", "tasi"];
+        all.extend_from_slice(args);
+        let config = Config::build(all.into_iter().map(String::from)).unwrap();
+        let mut printer = Printer::new(&config, true, Vec::new());
+        printer.begin_file("file.txt");
+        printer
+    }
+
+    fn line<'a>(
+        number: usize,
+        offset: usize,
+        text: &'a str,
+        spans: &'a [Range<usize>],
+    ) -> SinkLine<'a> {
+        SinkLine {
+            line_number: number,
+            byte_offset: offset,
+            line: text,
+            spans,
+            distances: &[],
+        }
+    }
+
+    #[test]
+    fn colored_line() {
+        let mut printer = printer(&["--color=always", "-n"]);
+        printer
+            .matched(&line(3, 0, "a tasi b tasi", &[2..6, 9..13]))
+            .unwrap();
+        let expected = "\x1b[35mfile.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m3\x1b[0m\x1b[36m:\x1b[0m\
+                        a \x1b[1;31mtasi\x1b[0m b \x1b[1;31mtasi\x1b[0m\n";
+        assert_eq!(expected, String::from_utf8(printer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn json_events() {
+        let mut printer = printer(&["--json", "-A", "1"]);
+        let span = 2..6;
+        let spans = std::slice::from_ref(&span);
+        printer.matched(&line(2, 10, "a tasi b", spans)).unwrap();
+        printer.context(&line(3, 19, "after", &[])).unwrap();
+        printer.end_file(1).unwrap();
+        // A file without any line has no events
+        printer.begin_file("other.txt");
+        printer.end_file(0).unwrap();
+        let mut out = printer.stats();
+        assert_eq!(2, out.files_searched);
+        out.add(Stats {
+            files_searched: 1,
+            files_with_matches: 1,
+            matched_lines: 4,
+        });
+        let mut printed = printer.into_inner();
+        write_summary(&mut printed, OutputMode::Json, &out).unwrap();
+
+        let events: Vec<serde_json::Value> = String::from_utf8(printed)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let expected = [
+            r#"{"type":"begin","data":{"path":"file.txt"}}"#,
+            r#"{"type":"match","data":{"path":"file.txt","line_number":2,"absolute_offset":10,
+                "line":"a tasi b","submatches":[{"match":"tasi","start":2,"end":6}]}}"#,
+            r#"{"type":"context","data":{"path":"file.txt","line_number":3,"absolute_offset":19,
+                "line":"after"}}"#,
+            r#"{"type":"end","data":{"path":"file.txt","matched_lines":1}}"#,
+            r#"{"type":"summary","data":{"files_searched":3,"files_with_matches":2,
+                "matched_lines":5}}"#,
+        ];
+        let expected: Vec<serde_json::Value> = expected
+            .iter()
+            .map(|event| serde_json::from_str(event).unwrap())
+            .collect();
+        assert_eq!(expected, events);
+    }
+
+    #[test]
+    fn fuzzy_distances() {
+        let mut printer = printer(&["--json"]);
+        let spans = [2..6, 9..12];
+        let found = SinkLine {
+            distances: &[1, 2],
+            ..line(1, 0, "a tazi b tsa", &spans)
+        };
+        printer.matched(&found).unwrap();
+        let printed = String::from_utf8(printer.into_inner()).unwrap();
+        let event: serde_json::Value =
+            serde_json::from_str(printed.lines().nth(1).unwrap()).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(
+            r#"[{"match":"tazi","start":2,"end":6,"distance":1},
+                {"match":"tsa","start":9,"end":12,"distance":2}]"#,
+        )
+        .unwrap();
+        assert_eq!(expected, event["data"]["submatches"]);
+    }
+
+    #[test]
+    fn never_colored() {
+        let mut printer = printer(&["--color=never"]);
+        assert!(!printer.wants_spans());
+        printer
+            .matched(&line(3, 0, "a tasi b tasi", &[2..6, 9..13]))
+            .unwrap();
+        assert_eq!(
+            "file.txt:a tasi b tasi\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use crate::{numbered_lines, search, search_case_insensitive, Match};
+use std::{collections::HashMap, error::Error, fmt, iter::Peekable, ops::Range, vec};
+
+// Where --query looks for its terms
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QueryScope {
+    // The query must hold for a single line, the default
+    #[default]
+    Line,
+    // The query must hold for the whole file, each term can be on any line
+    File,
+}
+
+impl QueryScope {
+    pub fn parse(name: &str) -> Option<QueryScope> {
+        match name {
+            "line" => Some(QueryScope::Line),
+            "file" => Some(QueryScope::File),
+            _ => None,
+        }
+    }
+}
+
+// A query of --query, like: unsafe AND NOT "SAFETY:"
+//
+// Terms are plain text, quoted when they have spaces, parentheses or
+// quotes, or are one of the operators. Inside quotes \" is a quote and
+// \\ a backslash. NOT comes first, then AND, then OR, so
+// a OR b AND NOT c is a OR (b AND (NOT c))
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    terms: Vec<String>,
+    expr: Expr,
+}
+
+// Parentheses and NOT can't be nested deeper than this. Evaluating the
+// query recurses once for each level, a deeper one could overflow the stack
+const MAX_DEPTH: usize = 256;
+
+// a AND b AND c is a single And of three, so only parentheses and NOT
+// make the tree deeper
+#[derive(Debug, PartialEq)]
+enum Expr {
+    // The index of the term in Query::terms
+    Term(usize),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+}
+
+// A line the query holds for. spans are where the terms that aren't
+// under a NOT matched, the ones worth highlighting
+#[derive(Debug, PartialEq)]
+pub struct QueryMatch<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+    pub spans: Vec<Range<usize>>,
+}
+
+// A query that can't be parsed. The column counts chars from 1
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Term(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+            end: text.chars().count() + 1,
+            terms: Vec::new(),
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if let Some((token, column)) = parser.tokens.next() {
+            let message = match token {
+                Token::Close => "unmatched ')'".to_string(),
+                _ => "expected AND, OR or the end of the query".to_string(),
+            };
+            return Err(QueryError { column, message });
+        }
+        Ok(Query {
+            terms: parser.terms,
+            expr,
+        })
+    }
+
+    // Every line the query holds for
+    pub fn lines<'a>(&self, content: &'a str, ignore_case: bool) -> Vec<QueryMatch<'a>> {
+        // Where each term matches: line number to span
+        let found: Vec<HashMap<usize, Range<usize>>> = self
+            .search(content, ignore_case)
+            .into_iter()
+            .map(|matches| {
+                matches
+                    .into_iter()
+                    .map(|found| (found.line_number, found.span))
+                    .collect()
+            })
+            .collect();
+        let positive = self.positive();
+        numbered_lines(content)
+            .filter(|(line_number, _, _)| {
+                self.expr
+                    .holds(&|term| found[term].contains_key(line_number))
+            })
+            .map(|(line_number, byte_offset, line)| {
+                let mut spans: Vec<Range<usize>> = (0..self.terms.len())
+                    .filter(|term| positive[*term])
+                    .filter_map(|term| found[term].get(&line_number).cloned())
+                    .collect();
+                // Sorted and without overlaps, the way the printer wants
+                spans.sort_by_key(|span| span.start);
+                let mut merged: Vec<Range<usize>> = Vec::new();
+                for span in spans {
+                    match merged.last_mut() {
+                        Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+                        _ => merged.push(span),
+                    }
+                }
+                QueryMatch {
+                    line_number,
+                    byte_offset,
+                    line,
+                    spans: merged,
+                }
+            })
+            .collect()
+    }
+
+    // If the query holds for the whole content
+    pub fn holds_in(&self, content: &str, ignore_case: bool) -> bool {
+        let found = self.search(content, ignore_case);
+        self.expr.holds(&|term| !found[term].is_empty())
+    }
+
+    // Each term goes through the plain search
+    fn search<'a>(&self, content: &'a str, ignore_case: bool) -> Vec<Vec<Match<'a>>> {
+        self.terms
+            .iter()
+            .map(|term| {
+                if ignore_case {
+                    search_case_insensitive(content, term)
+                } else {
+                    search(content, term)
+                }
+            })
+            .collect()
+    }
+
+    // For each term, if it's under an even number of NOTs
+    fn positive(&self) -> Vec<bool> {
+        let mut positive = vec![false; self.terms.len()];
+        self.expr.mark(true, &mut positive);
+        positive
+    }
+}
+
+impl Expr {
+    fn holds(&self, has: &impl Fn(usize) -> bool) -> bool {
+        match self {
+            Expr::Term(term) => has(*term),
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.holds(has)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.holds(has)),
+            Expr::Not(expr) => !expr.holds(has),
+        }
+    }
+
+    fn mark(&self, polarity: bool, positive: &mut [bool]) {
+        match self {
+            Expr::Term(term) => positive[*term] |= polarity,
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                for expr in exprs {
+                    expr.mark(polarity, positive);
+                }
+            }
+            Expr::Not(expr) => expr.mark(!polarity, positive),
+        }
+    }
+}
+
+// Splits the query into tokens, each one with its column
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let mut tokens = Vec::new();
+    // Columns count chars, not bytes
+    let mut chars = text.chars().enumerate().peekable();
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '"' => {
+                let mut term = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => term.push(escaped),
+                            Some((index, _)) => {
+                                return Err(QueryError {
+                                    column: index + 1,
+                                    message: "only \\\" and \\\\ can be escaped".to_string(),
+                                })
+                            }
+                            None => return Err(unclosed(column)),
+                        },
+                        Some((_, c)) => term.push(c),
+                        None => return Err(unclosed(column)),
+                    }
+                }
+                if term.is_empty() {
+                    return Err(QueryError {
+                        column,
+                        message: "empty term".to_string(),
+                    });
+                }
+                Token::Term(term)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| !ends_word(*c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term(word),
+                }
+            }
+        };
+        tokens.push((token, column));
+    }
+    Ok(tokens)
+}
+
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"')
+}
+
+fn unclosed(column: usize) -> QueryError {
+    QueryError {
+        column,
+        message: "unclosed quote".to_string(),
+    }
+}
+
+// A recursive descent parser, one function for each level of precedence:
+//
+//     or   = and { OR and }
+//     and  = not { AND not }
+//     not  = NOT not | atom
+//     atom = TERM | ( or )
+struct Parser {
+    tokens: Peekable<vec::IntoIter<(Token, usize)>>,
+    // The column right after the query, where a missing term is
+    end: usize,
+    terms: Vec<String>,
+    // How many parentheses and NOTs the parser is inside of
+    depth: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.and()?];
+        while self
+            .tokens
+            .next_if(|(token, _)| *token == Token::Or)
+            .is_some()
+        {
+            exprs.push(self.and()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.not()?];
+        while self
+            .tokens
+            .next_if(|(token, _)| *token == Token::And)
+            .is_some()
+        {
+            exprs.push(self.not()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    fn not(&mut self) -> Result<Expr, QueryError> {
+        if let Some((_, column)) = self.tokens.next_if(|(token, _)| *token == Token::Not) {
+            self.enter(column)?;
+            let expr = Expr::Not(Box::new(self.not()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.atom()
+    }
+
+    // Goes one level deeper, for the ( or NOT at column
+    fn enter(&mut self, column: usize) -> Result<(), QueryError> {
+        if self.depth == MAX_DEPTH {
+            return Err(QueryError {
+                column,
+                message: format!("nested more than {MAX_DEPTH} levels deep"),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn atom(&mut self) -> Result<Expr, QueryError> {
+        match self.tokens.next() {
+            Some((Token::Term(term), _)) => {
+                self.terms.push(term);
+                Ok(Expr::Term(self.terms.len() - 1))
+            }
+            Some((Token::Open, column)) => {
+                self.enter(column)?;
+                let expr = self.or()?;
+                self.depth -= 1;
+                match self.tokens.next() {
+                    Some((Token::Close, _)) => Ok(expr),
+                    Some((_, column)) => Err(QueryError {
+                        column,
+                        message: "expected AND, OR or ')'".to_string(),
+                    }),
+                    None => Err(QueryError {
+                        column,
+                        message: "unclosed '('".to_string(),
+                    }),
+                }
+            }
+            Some((token, column)) => Err(QueryError {
+                column,
+                message: format!("expected a term, found {}", describe(&token)),
+            }),
+            None => Err(QueryError {
+                column: self.end,
+                message: "expected a term, found the end of the query".to_string(),
+            }),
+        }
+    }
+}
+
+fn describe(token: &Token) -> &'static str {
+    match token {
+        Token::Term(_) => "a term",
+        Token::And => "AND",
+        Token::Or => "OR",
+        Token::Not => "NOT",
+        Token::Open => "'('",
+        Token::Close => "')'",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (usize, String) {
+        let err = Query::parse(text).unwrap_err();
+        (err.column, err.message)
+    }
+
+    #[test]
+    fn precedence() {
+        let query = Query::parse("a OR b AND NOT c").unwrap();
+        let expected = Expr::Or(vec![
+            Expr::Term(0),
+            Expr::And(vec![Expr::Term(1), Expr::Not(Box::new(Expr::Term(2)))]),
+        ]);
+        assert_eq!(expected, query.expr);
+        let query = Query::parse("a AND b AND (c OR d OR e)").unwrap();
+        let expected = Expr::And(vec![
+            Expr::Term(0),
+            Expr::Term(1),
+            Expr::Or(vec![Expr::Term(2), Expr::Term(3), Expr::Term(4)]),
+        ]);
+        assert_eq!(expected, query.expr);
+        let query = Query::parse(r#"(a OR "b c") AND "say \"AND\"""#).unwrap();
+        assert_eq!(vec!["a", "b c", "say \"AND\""], query.terms);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            (7, "expected a term, found the end of the query".into()),
+            error("a AND ")
+        );
+        assert_eq!(
+            (3, "expected AND, OR or the end of the query".into()),
+            error("a b")
+        );
+        assert_eq!((1, "unclosed '('".into()), error("(a OR b"));
+        assert_eq!((3, "unmatched ')'".into()), error("a )"));
+        assert_eq!((7, "unclosed quote".into()), error("NOT a \"b"));
+        assert_eq!((5, "expected a term, found OR".into()), error("NOT OR"));
+        // Columns count chars, not bytes
+        assert_eq!(
+            (6, "expected AND, OR or the end of the query".into()),
+            error("café ü x")
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        let deep = |levels: usize| format!("{}a{}", "(".repeat(levels), ")".repeat(levels));
+        assert!(Query::parse(&deep(MAX_DEPTH)).is_ok());
+        let message = format!("nested more than {MAX_DEPTH} levels deep");
+        assert_eq!((MAX_DEPTH + 1, message.clone()), error(&deep(10_000)));
+        let nots = format!("{}a", "NOT ".repeat(10_000));
+        assert_eq!((MAX_DEPTH * 4 + 1, message), error(&nots));
+        // Long chains don't make the query any deeper
+        let chain = vec!["a"; 100_000].join(" AND ");
+        assert!(Query::parse(&chain).unwrap().holds_in("a", false));
+    }
+
+    #[test]
+    fn lines_and_files() {
+        let content = "unsafe { read() }\n// SAFETY: checked\nunsafe { SAFETY: x }\nsafe\n";
+        let query = Query::parse(r#"unsafe AND NOT "SAFETY:""#).unwrap();
+        let lines = query.lines(content, false);
+        assert_eq!(1, lines.len());
+        assert_eq!(
+            (1, 0, 0..6),
+            (
+                lines[0].line_number,
+                lines[0].byte_offset,
+                lines[0].spans[0].clone()
+            )
+        );
+        // In the whole file SAFETY: is there
+        assert!(!query.holds_in(content, false));
+        let query = Query::parse("read OR checked AND NOT nothing").unwrap();
+        assert!(query.holds_in(content, false));
+
+        // Overlapping spans are merged, negated terms aren't highlighted
+        let query = Query::parse("SAFE AND (SAFETY OR NOT unsafe)").unwrap();
+        let lines = query.lines(content, true);
+        let found: Vec<_> = lines
+            .iter()
+            .map(|line| format!("{}:{:?}", line.line_number, line.spans))
+            .collect();
+        assert_eq!(vec!["2:[3..9]", "3:[2..6, 9..15]", "4:[0..4]"], found);
+    }
+}
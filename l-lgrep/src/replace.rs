@@ -0,0 +1,195 @@
+use crate::{stream, Matcher};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+use tempfile::NamedTempFile;
+
+// A line changed by --replace
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub line_number: usize,
+    pub old: String,
+    pub new: String,
+}
+
+// Copies the reader into out, replacing the matches of every line.
+// The line breaks are kept as they were, "\r\n" stays "\r\n", and lines
+// that aren't valid UTF-8 are copied untouched.
+//
+// Returns the lines that changed
+pub fn replace_reader<R: BufRead, W: Write>(
+    mut reader: R,
+    matcher: &Matcher,
+    template: &str,
+    out: &mut W,
+) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        let mut end = buffer.len();
+        if buffer[..end].ends_with(b"\n") {
+            end -= 1;
+        }
+        if buffer[..end].ends_with(b"\r") {
+            end -= 1;
+        }
+        let (line, line_break) = buffer.split_at(end);
+        let Ok(line) = std::str::from_utf8(line) else {
+            out.write_all(&buffer)?;
+            continue;
+        };
+        let replaced = matcher.replace_all(line, template);
+        out.write_all(replaced.as_bytes())?;
+        out.write_all(line_break)?;
+        if replaced != line {
+            changes.push(Change {
+                line_number,
+                old: line.to_string(),
+                new: replaced.into_owned(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+// Runs the replacement over a file. Binary files are left alone.
+//
+// When write is true the new content goes to a temporary file in the
+// directory of the file, which then takes the place of the original with a rename.
+// A rename is atomic, so if lilgrep is interrupted the original file is
+// either untouched or fully replaced, never half written
+pub fn replace_file(
+    path: &Path,
+    matcher: &Matcher,
+    template: &str,
+    write: bool,
+) -> io::Result<Vec<Change>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if stream::is_binary(&mut reader)? {
+        return Ok(Vec::new());
+    }
+    if !write {
+        return replace_reader(reader, matcher, template, &mut io::sink());
+    }
+
+    // A symbolic link is followed, it's the file it points to that
+    // changes. Renaming over the link would replace the link itself
+    let target = fs::canonicalize(path)?;
+    let directory = target.parent().unwrap_or(Path::new("/"));
+    // The temporary file is deleted when dropped, unless persisted
+    let temporary = NamedTempFile::new_in(directory)?;
+    let mut out = BufWriter::new(temporary);
+    let changes = replace_reader(reader, matcher, template, &mut out)?;
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    let temporary = out.into_inner().map_err(|err| err.into_error())?;
+    temporary.as_file().sync_all()?;
+    // The new file must keep the permissions of the original one
+    fs::set_permissions(temporary.path(), fs::metadata(&target)?.permissions())?;
+    temporary.persist(&target).map_err(|err| err.error)?;
+    Ok(changes)
+}
+
+// Prints the changes of a file like a diff
+pub fn print_preview<W: Write>(out: &mut W, path: &str, changes: &[Change]) -> io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "--- {path}")?;
+    writeln!(out, "+++ {path}")?;
+    for change in changes {
+        writeln!(out, "@@ -{0} +{0} @@", change.line_number)?;
+        writeln!(out, "-{}", change.old)?;
+        writeln!(out, "+{}", change.new)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(pattern: &str) -> Matcher {
+        Matcher::new(&[pattern.to_string()], true, false).unwrap()
+    }
+
+    #[test]
+    fn keeps_line_breaks() {
+        let content = "let tasi = 1;\r\nno match\nlet pasin = 2;";
+        let mut out = Vec::new();
+        let changes = replace_reader(
+            content.as_bytes(),
+            &matcher(r"let (\w+)"),
+            "const $1",
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            "const tasi = 1;\r\nno match\nconst pasin = 2;",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(
+            Change {
+                line_number: 3,
+                old: "let pasin = 2;".to_string(),
+                new: "const pasin = 2;".to_string(),
+            },
+            changes[1]
+        );
+    }
+
+    #[test]
+    fn preview_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "my name is tasi\nnothing here\n").unwrap();
+
+        // Without write the file stays the same
+        let changes = replace_file(&path, &matcher("tasi"), "Tasi", false).unwrap();
+        assert_eq!(1, changes.len());
+        assert_eq!(
+            "my name is tasi\nnothing here\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        let mut preview = Vec::new();
+        print_preview(&mut preview, "file.txt", &changes).unwrap();
+        assert_eq!(
+            "--- file.txt\n+++ file.txt\n@@ -1 +1 @@\n-my name is tasi\n+my name is Tasi\n",
+            String::from_utf8(preview).unwrap()
+        );
+
+        replace_file(&path, &matcher("tasi"), "Tasi", true).unwrap();
+        assert_eq!(
+            "my name is Tasi\nnothing here\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        // No temporary file is left behind
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "tasi\n").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        replace_file(&link, &matcher("tasi"), "Tasi", true).unwrap();
+        assert_eq!("Tasi\n", fs::read_to_string(&real).unwrap());
+        // The link is still a link
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
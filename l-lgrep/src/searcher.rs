@@ -0,0 +1,361 @@
+use crate::{
+    compress::{self, Format},
+    encoding::{self, Decoded},
+    stream, Encoding, LilgrepError, Matcher, SearchOptions, Sink,
+};
+use memmap2::Mmap;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+// Searches inputs with a matcher and sends what it finds to a Sink,
+// without printing anything. It's what the command line uses for every
+// file, and what a library can use to embed lilgrep:
+//
+//     let matcher = Matcher::new(&["ERROR".to_string()], false, false)?;
+//     let searcher = Searcher::builder(matcher).after_context(1).build();
+//     searcher.search_path("app.log", &mut my_sink)?;
+//
+// A Searcher can be shared between threads, each search has its own sink
+#[derive(Debug)]
+pub struct Searcher {
+    matcher: Matcher,
+    options: SearchOptions,
+    encoding: Encoding,
+    text: bool,
+    decompress: bool,
+    mmap: MmapChoice,
+}
+
+// Files smaller than this are read, mapping them costs more than it saves
+const MMAP_MIN_LEN: u64 = 1024 * 1024;
+
+// How search_path gets to the content of a file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MmapChoice {
+    // Memory maps files of at least 1 MiB. The search then runs over the
+    // pages of the file directly, without copying the content around.
+    // Good for one big file
+    #[default]
+    Auto,
+    // Always reads through a buffer. Better when searching many files at
+    // once: mapping and unmapping each one, from many threads, is slower
+    // than reading them
+    Never,
+}
+
+// Builds a Searcher. Everything but the matcher is optional, by default
+// there's no context, no limit, UTF-8 with BOM sniffing, binary files are
+// reported to the sink and compressed files are searched as they are
+#[derive(Debug)]
+pub struct SearcherBuilder {
+    searcher: Searcher,
+}
+
+impl SearcherBuilder {
+    // Replaces the line options at once
+    pub fn options(mut self, options: SearchOptions) -> SearcherBuilder {
+        self.searcher.options = options;
+        self
+    }
+
+    pub fn before_context(mut self, lines: usize) -> SearcherBuilder {
+        self.searcher.options.before_context = lines;
+        self
+    }
+
+    pub fn after_context(mut self, lines: usize) -> SearcherBuilder {
+        self.searcher.options.after_context = lines;
+        self
+    }
+
+    // The lines that don't match are the selected ones
+    pub fn invert(mut self, yes: bool) -> SearcherBuilder {
+        self.searcher.options.invert = yes;
+        self
+    }
+
+    // Stops each input after this many selected lines
+    pub fn max_count(mut self, max: Option<usize>) -> SearcherBuilder {
+        self.searcher.options.max_count = max;
+        self
+    }
+
+    // Matches can span several lines, see SearchOptions::multiline
+    pub fn multiline(mut self, yes: bool) -> SearcherBuilder {
+        self.searcher.options.multiline = yes;
+        self
+    }
+
+    // The encoding of inputs without a BOM
+    pub fn encoding(mut self, encoding: Encoding) -> SearcherBuilder {
+        self.searcher.encoding = encoding;
+        self
+    }
+
+    // Binary inputs are searched like text, Sink::binary is never called
+    pub fn binary_as_text(mut self, yes: bool) -> SearcherBuilder {
+        self.searcher.text = yes;
+        self
+    }
+
+    // Inputs compressed with gzip, bzip2 or zstd are decompressed first
+    pub fn decompress(mut self, yes: bool) -> SearcherBuilder {
+        self.searcher.decompress = yes;
+        self
+    }
+
+    pub fn mmap(mut self, choice: MmapChoice) -> SearcherBuilder {
+        self.searcher.mmap = choice;
+        self
+    }
+
+    pub fn build(self) -> Searcher {
+        self.searcher
+    }
+}
+
+impl Searcher {
+    pub fn builder(matcher: Matcher) -> SearcherBuilder {
+        SearcherBuilder {
+            searcher: Searcher {
+                matcher,
+                options: SearchOptions::default(),
+                encoding: Encoding::Auto,
+                text: false,
+                decompress: false,
+                mmap: MmapChoice::Auto,
+            },
+        }
+    }
+
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    // Searches the file at path. Errors say which file they are about
+    pub fn search_path<P: AsRef<Path>, S: Sink>(
+        &self,
+        path: P,
+        sink: &mut S,
+    ) -> Result<usize, LilgrepError> {
+        let path = path.as_ref();
+        self.search_file(path, sink)
+            .map_err(|err| LilgrepError::io(&path.display().to_string(), err))
+    }
+
+    fn search_file<S: Sink>(&self, path: &Path, sink: &mut S) -> io::Result<usize> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if self.mmap == MmapChoice::Auto && len >= MMAP_MIN_LEN {
+            // Safety: the map is only read, and only while the file is
+            // open. If another process truncates the file meanwhile, the
+            // reading can crash, the same risk grep and ripgrep take
+            let map = unsafe { Mmap::map(&file)? };
+            return self.search_slice(&map, sink);
+        }
+        self.search_reader(BufReader::new(file), sink)
+    }
+
+    // Searches text already in memory
+    pub fn search_str<S: Sink>(&self, content: &str, sink: &mut S) -> io::Result<usize> {
+        self.search_slice(content.as_bytes(), sink)
+    }
+
+    // Searches bytes already in memory. Plain UTF-8 is searched where it
+    // is, other encodings and compressed content go through a reader
+    pub fn search_slice<S: Sink>(&self, bytes: &[u8], sink: &mut S) -> io::Result<usize> {
+        if self.decompress && Format::sniff(bytes).is_some() {
+            return self.search_reader(bytes, sink);
+        }
+        let mut bytes = match encoding::decode(bytes, self.encoding)? {
+            // Without the BOM, if there was one
+            Decoded::Plain(bytes) => bytes,
+            transcoded => return self.search_decoded(transcoded, sink),
+        };
+        if !self.text && stream::is_binary(&mut bytes)? {
+            sink.binary()?;
+        }
+        if self.options.multiline {
+            let content = String::from_utf8_lossy(bytes);
+            return stream::search_buffer(&content, &self.matcher, sink, &self.options);
+        }
+        stream::search_slice(bytes, &self.matcher, sink, &self.options)
+    }
+
+    // Searches any reader, line by line. Returns how many lines were
+    // selected. The errors are the ones of the reader and of the sink
+    pub fn search_reader<R: BufRead, S: Sink>(&self, reader: R, sink: &mut S) -> io::Result<usize> {
+        if self.decompress {
+            let reader = compress::decompress(reader)?;
+            return self.search_text(reader, sink);
+        }
+        self.search_text(reader, sink)
+    }
+
+    // Decodes the input to UTF-8 and looks for a binary file before the
+    // search itself
+    fn search_text<R: BufRead, S: Sink>(&self, reader: R, sink: &mut S) -> io::Result<usize> {
+        let reader = encoding::decode(reader, self.encoding)?;
+        self.search_decoded(reader, sink)
+    }
+
+    fn search_decoded<R: BufRead, S: Sink>(
+        &self,
+        mut reader: Decoded<R>,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        if !self.text && stream::is_binary(&mut reader)? {
+            sink.binary()?;
+        }
+        if self.options.multiline {
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)?;
+            let content = String::from_utf8_lossy(&content);
+            return stream::search_buffer(&content, &self.matcher, sink, &self.options);
+        }
+        stream::search_reader(reader, &self.matcher, sink, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MatcherOptions, SinkLine};
+
+    // A sink that keeps everything, the way a library would use it
+    #[derive(Default)]
+    struct Collect {
+        lines: Vec<String>,
+        binary: bool,
+    }
+
+    impl Sink for Collect {
+        fn matched(&mut self, line: &SinkLine) -> io::Result<bool> {
+            let (number, text, spans) = (line.line_number, line.line, line.spans);
+            self.lines.push(format!("{number}:{text} {spans:?}"));
+            Ok(true)
+        }
+
+        fn context(&mut self, line: &SinkLine) -> io::Result<()> {
+            self.lines
+                .push(format!("{}-{}", line.line_number, line.line));
+            Ok(())
+        }
+
+        fn binary(&mut self) -> io::Result<()> {
+            self.binary = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn structured_results() {
+        let matcher = Matcher::new(&["tasi".to_string()], false, false).unwrap();
+        let searcher = Searcher::builder(matcher).after_context(1).build();
+        let mut sink = Collect::default();
+        let count = searcher
+            .search_str("one\ntasi and tasi\ntwo\nthree", &mut sink)
+            .unwrap();
+        assert_eq!(1, count);
+        assert_eq!(vec!["2:tasi and tasi [0..4, 9..13]", "3-two"], sink.lines);
+        assert!(!sink.binary);
+    }
+
+    #[test]
+    fn multiline() {
+        let content = "fn main(\n    x: u8,\n) {}\nfn other() {}\n";
+        let matcher = Matcher::new(&[r"fn \w+\(\s+x".to_string()], true, false).unwrap();
+        let searcher = Searcher::builder(matcher).multiline(true).build();
+        let mut sink = Collect::default();
+        assert_eq!(2, searcher.search_str(content, &mut sink).unwrap());
+        assert_eq!(vec!["1:fn main( [0..8]", "2:    x: u8, [0..5]"], sink.lines);
+
+        // Without -U the pattern can't cross the line break
+        let matcher = Matcher::new(&[r"fn \w+\(\s+x".to_string()], true, false).unwrap();
+        let searcher = Searcher::builder(matcher).build();
+        assert_eq!(0, searcher.search_str(content, &mut sink).unwrap());
+
+        // The lines that no match touches, and their context
+        let matcher = Matcher::new(&[r"\(\n".to_string()], true, false).unwrap();
+        let searcher = Searcher::builder(matcher)
+            .multiline(true)
+            .invert(true)
+            .before_context(1)
+            .max_count(Some(1))
+            .build();
+        let mut sink = Collect::default();
+        assert_eq!(1, searcher.search_str(content, &mut sink).unwrap());
+        assert_eq!(vec!["1-fn main(", "2:    x: u8, []"], sink.lines);
+    }
+
+    #[test]
+    fn multiline_empty_lines() {
+        let options = MatcherOptions {
+            regex: true,
+            multiline: true,
+            ..MatcherOptions::default()
+        };
+        let search = |content: &str| {
+            let matcher = Matcher::with_options(&["^$".to_string()], &options).unwrap();
+            let searcher = Searcher::builder(matcher).multiline(true).build();
+            let mut sink = Collect::default();
+            searcher.search_str(content, &mut sink).unwrap();
+            sink.lines
+        };
+        // Nothing comes after the last line break, like in grep
+        assert!(search("a\n").is_empty());
+        assert_eq!(vec!["2: []"], search("a\n\nb\n"));
+        assert_eq!(vec!["2: []"], search("a\n\n"));
+    }
+
+    #[test]
+    fn paths_and_binary() {
+        let matcher = Matcher::new(&["ERROR".to_string()], false, false).unwrap();
+        let searcher = Searcher::builder(matcher).decompress(true).build();
+        let mut sink = Collect::default();
+        let count = searcher
+            .search_path("resources/compressed/app.log.1.gz", &mut sink)
+            .unwrap();
+        assert_eq!(2, count);
+
+        let err = searcher.search_path("resources/missing.log", &mut sink);
+        assert!(matches!(err, Err(LilgrepError::NotFound { .. })));
+
+        let matcher = Matcher::new(&["tasi".to_string()], false, false).unwrap();
+        let searcher = Searcher::builder(matcher).build();
+        let mut sink = Collect::default();
+        searcher
+            .search_path("resources/tree/data.bin", &mut sink)
+            .unwrap();
+        assert!(sink.binary);
+    }
+
+    #[test]
+    fn mapped_and_read() {
+        // Big enough to be mapped, with a BOM and a match on each end
+        let mut content = "\u{FEFF}tasi first\n".to_string();
+        let line = "some filler line without the name\n";
+        content.push_str(&line.repeat(MMAP_MIN_LEN as usize / line.len() + 1));
+        content.push_str("tasi last\n");
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, content.as_bytes()).unwrap();
+
+        let mut found = Vec::new();
+        for choice in [MmapChoice::Auto, MmapChoice::Never] {
+            let matcher = Matcher::new(&["tasi".to_string()], false, false).unwrap();
+            let searcher = Searcher::builder(matcher).mmap(choice).build();
+            let mut sink = Collect::default();
+            assert_eq!(2, searcher.search_path(file.path(), &mut sink).unwrap());
+            found.push(sink.lines);
+        }
+        assert_eq!(found[0], found[1]);
+        assert_eq!("1:tasi first [0..4]", found[0][0]);
+    }
+}
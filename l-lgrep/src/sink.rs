@@ -0,0 +1,46 @@
+use std::{io, ops::Range};
+
+// A line handed to a Sink. line_number starts at 1 and byte_offset is
+// where the line starts in the input. The line has no line break
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkLine<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+    // The parts of the line that matched, as byte ranges inside line.
+    // Empty for context lines, inverted matches and sinks that don't
+    // want them
+    pub spans: &'a [Range<usize>],
+    // With --fuzzy, how many edits away from the pattern each span is.
+    // Empty otherwise
+    pub distances: &'a [usize],
+}
+
+// Where a Searcher sends what it finds. The lines are only borrowed for
+// the call, a sink keeping them must copy them.
+//
+// The printer of the command line is a sink too, a library can use its
+// own to get the results without anything being printed
+pub trait Sink {
+    // Called for every selected line. Returning false stops the search
+    // of the current input right away
+    fn matched(&mut self, line: &SinkLine) -> io::Result<bool>;
+
+    // Called for every line printed around the matches, when the
+    // searcher has before or after context
+    fn context(&mut self, _line: &SinkLine) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Called once, before any line, when the input looks like a binary
+    // file and the searcher wasn't told to treat it as text
+    fn binary(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Finding every match of a line costs more than finding the first
+    // one, a sink that doesn't need the spans can say so
+    fn wants_spans(&self) -> bool {
+        true
+    }
+}
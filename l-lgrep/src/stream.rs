@@ -0,0 +1,428 @@
+use crate::{Matcher, Sink, SinkLine};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead},
+    ops::Range,
+};
+
+// How many bytes are looked at to decide if the input is binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+// What the search does with each line, apart from the matcher
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    // Lines of context printed before and after each match
+    pub before_context: usize,
+    pub after_context: usize,
+    // -v, the lines that don't match are the selected ones
+    pub invert: bool,
+    // -m, stops reading after this many selected lines
+    pub max_count: Option<usize>,
+    // -U, matches can span several lines. The whole input is read before
+    // the search, so it only works with inputs that fit in memory
+    pub multiline: bool,
+}
+
+// Searches the reader line by line. Only the current line and the lines
+// kept for the before context are in memory at any time, so the size of
+// the input doesn't matter, it can be a multi-GB log or an endless pipe.
+//
+// Returns how many lines were selected
+pub fn search_reader<R: BufRead, S: Sink>(
+    mut reader: R,
+    matcher: &Matcher,
+    sink: &mut S,
+    options: &SearchOptions,
+) -> io::Result<usize> {
+    let mut selector = Selector::new(options);
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+    let mut byte_offset = 0;
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let line_offset = byte_offset;
+        byte_offset += read;
+        if !selector.search_line(sink, matcher, line_number, line_offset, &buffer)? {
+            break;
+        }
+    }
+    Ok(selector.matches)
+}
+
+// Same as search_reader, for content that is already in memory, like a
+// memory mapped file. The lines are borrowed from bytes, valid UTF-8 is
+// never copied
+pub fn search_slice<S: Sink>(
+    bytes: &[u8],
+    matcher: &Matcher,
+    sink: &mut S,
+    options: &SearchOptions,
+) -> io::Result<usize> {
+    let plain = !options.invert && options.before_context == 0 && options.after_context == 0;
+    if plain && matcher.is_literal() {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return search_ahead(text, matcher, sink, options);
+        }
+    }
+    let mut selector = Selector::new(options);
+    let mut byte_offset = 0;
+    for (index, raw) in bytes.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        let line_offset = byte_offset;
+        byte_offset += raw.len();
+        if !selector.search_line(sink, matcher, index + 1, line_offset, raw)? {
+            break;
+        }
+    }
+    Ok(selector.matches)
+}
+
+// Looks for the next match in the whole rest of the text instead of line
+// by line, skipping every line before it at once. Only for literals: a
+// literal found in the text is also found in its line, which isn't true
+// for regular expressions with anchors like ^. Without context there's
+// nothing to do with the skipped lines but count them
+fn search_ahead<S: Sink>(
+    text: &str,
+    matcher: &Matcher,
+    sink: &mut S,
+    options: &SearchOptions,
+) -> io::Result<usize> {
+    let mut selector = Selector::new(options);
+    // Where the next line not looked at yet starts, and its number
+    let mut start = 0;
+    let mut line_number = 1;
+    while start < text.len() {
+        let Some(found) = matcher.find(&text[start..]) else {
+            break;
+        };
+        let at = start + found.start;
+        let line_start = text[start..at]
+            .rfind('\n')
+            .map_or(start, |end| start + end + 1);
+        line_number += memchr::memchr_iter(b'\n', &text.as_bytes()[start..line_start]).count();
+        let line_end = text[at..].find('\n').map_or(text.len(), |end| at + end + 1);
+        let line = &text.as_bytes()[line_start..line_end];
+        // The match may still cross the end of the line, the line is
+        // searched again on its own
+        if !selector.search_line(sink, matcher, line_number, line_start, line)? {
+            break;
+        }
+        start = line_end;
+        line_number += 1;
+    }
+    Ok(selector.matches)
+}
+
+// The -U search: the matcher runs over the whole content at once, so a
+// match can span several lines. Every line a match touches is selected,
+// each one with the part of the match that falls inside it
+pub fn search_buffer<S: Sink>(
+    content: &str,
+    matcher: &Matcher,
+    sink: &mut S,
+    options: &SearchOptions,
+) -> io::Result<usize> {
+    let lines: Vec<(usize, &str)> = content
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .collect();
+    // The spans of the touched lines, by line index, relative to the line
+    let mut touched: BTreeMap<usize, Vec<Range<usize>>> = BTreeMap::new();
+    for span in matcher.find_every(content) {
+        // After the last line break there's no line, an empty match there,
+        // like the one of ^$, touches nothing
+        if span.start == content.len() && content.ends_with('\n') {
+            continue;
+        }
+        // The line the match starts in, and the first line after it ends
+        let first = lines.partition_point(|(start, _)| *start <= span.start);
+        let last = lines.partition_point(|(start, _)| *start < span.end.max(span.start + 1));
+        let covered = lines
+            .iter()
+            .enumerate()
+            .take(last)
+            .skip(first.saturating_sub(1));
+        for (index, &(start, line)) in covered {
+            let text_len = text(line).len();
+            let from = span.start.max(start) - start;
+            let to = (span.end.min(start + line.len()) - start).min(text_len);
+            let spans = touched.entry(index).or_default();
+            if from < to {
+                spans.push(from..to);
+            }
+        }
+    }
+
+    let mut selector = Selector::new(options);
+    for (index, (offset, line)) in lines.iter().enumerate() {
+        if selector.finished() {
+            break;
+        }
+        let line = text(line);
+        let spans = touched.remove(&index);
+        let selected = spans.is_some() != options.invert;
+        let spans = match spans {
+            Some(spans) if !options.invert => spans,
+            _ => Vec::new(),
+        };
+        let found = SinkLine {
+            line_number: index + 1,
+            byte_offset: *offset,
+            line,
+            spans: &spans,
+            distances: &[],
+        };
+        if !selector.line(sink, &found, selected)? {
+            break;
+        }
+    }
+    Ok(selector.matches)
+}
+
+// The line without its line break
+fn text(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Decides which lines go to the sink as matches and which ones as
+// context, the same way for both kinds of search
+struct Selector<'o> {
+    options: &'o SearchOptions,
+    // Lines waiting to be printed as before context, if a match comes
+    before: VecDeque<(usize, usize, String)>,
+    // How many lines after the last match must still be printed
+    after: usize,
+    matches: usize,
+}
+
+impl<'o> Selector<'o> {
+    fn new(options: &'o SearchOptions) -> Selector<'o> {
+        Selector {
+            options,
+            before: VecDeque::with_capacity(options.before_context),
+            after: 0,
+            matches: 0,
+        }
+    }
+
+    // True once max_count lines were selected
+    fn done(&self) -> bool {
+        self.options
+            .max_count
+            .is_some_and(|max| self.matches >= max)
+    }
+
+    // True once the after context of the last allowed match was printed
+    fn finished(&self) -> bool {
+        self.done() && self.after == 0
+    }
+
+    // Matches a raw line, with its line break, and takes it. Returns
+    // false when the search is over
+    fn search_line<S: Sink>(
+        &mut self,
+        sink: &mut S,
+        matcher: &Matcher,
+        line_number: usize,
+        byte_offset: usize,
+        raw: &[u8],
+    ) -> io::Result<bool> {
+        // After the last allowed match only its after context is still
+        // printed, then the reading stops
+        if self.finished() {
+            return Ok(false);
+        }
+        let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+        let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+        // Lines that aren't valid UTF-8 are still searched, the invalid
+        // bytes are replaced by U+FFFD
+        let line = String::from_utf8_lossy(raw);
+
+        let invert = self.options.invert;
+        let selected = matcher.find(&line).is_some() != invert;
+        // Inverted lines have nothing to highlight
+        let (spans, distances) = if selected && !self.done() && sink.wants_spans() && !invert {
+            matcher.find_scored(&line)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let found = SinkLine {
+            line_number,
+            byte_offset,
+            line: &line,
+            spans: &spans,
+            distances: &distances,
+        };
+        self.line(sink, &found, selected)
+    }
+
+    // Takes the next line, with what was found in it when it's
+    // selected. Returns false when the sink wants to stop
+    fn line<S: Sink>(
+        &mut self,
+        sink: &mut S,
+        found: &SinkLine,
+        selected: bool,
+    ) -> io::Result<bool> {
+        if selected && !self.done() {
+            self.matches += 1;
+            for (number, offset, context) in self.before.drain(..) {
+                sink.context(&SinkLine {
+                    line_number: number,
+                    byte_offset: offset,
+                    line: &context,
+                    spans: &[],
+                    distances: &[],
+                })?;
+            }
+            let go_on = sink.matched(found)?;
+            self.after = self.options.after_context;
+            // Stopping right away, instead of at the next line, matters
+            // when the next line may never come, like with --follow
+            return Ok(go_on && !self.finished());
+        }
+        if self.after > 0 {
+            sink.context(&SinkLine {
+                spans: &[],
+                distances: &[],
+                ..*found
+            })?;
+            self.after -= 1;
+            return Ok(!self.finished());
+        }
+        if self.options.before_context > 0 {
+            if self.before.len() == self.options.before_context {
+                self.before.pop_front();
+            }
+            self.before
+                .push_back((found.line_number, found.byte_offset, found.line.to_string()));
+        }
+        Ok(true)
+    }
+}
+
+// A NUL byte at the beginning of the input means it's a binary file.
+// This only peeks at the reader buffer, nothing is consumed
+pub fn is_binary<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    let head = reader.fill_buf()?;
+    Ok(head[..head.len().min(BINARY_SNIFF_LEN)].contains(&0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{printer::Printer, Config};
+
+    fn output(content: &str, args: &[&str]) -> String {
+        search_with(content, args, false)
+    }
+
+    // Searches with search_slice instead of search_reader when in_memory
+    fn search_with(content: &str, args: &[&str], in_memory: bool) -> String {
+        let mut all = vec!["lilgrep", "-", "tasi"];
+        all.extend_from_slice(args);
+        let config = Config::build(all.into_iter().map(String::from)).unwrap();
+        let matcher = Matcher::literal("tasi");
+        let mut printer = Printer::new(&config, false, Vec::new());
+        printer.begin_file("file.txt");
+        let options = config.search_options();
+        let count = if in_memory {
+            search_slice(content.as_bytes(), &matcher, &mut printer, &options)
+        } else {
+            search_reader(content.as_bytes(), &matcher, &mut printer, &options)
+        };
+        printer.end_file(count.unwrap()).unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn plain_lines() {
+        let content = "some text\nmore tasi there\nand tasi again";
+        assert_eq!("more tasi there\nand tasi again\n", output(content, &[]));
+    }
+
+    #[test]
+    fn context_groups() {
+        let content = "a\nb\ntasi\nc\nd\ne\nf\ntasi\ng\ntasi\nh\n";
+        let expected =
+            "2-2-b\n3:4:tasi\n4-9-c\n--\n7-15-f\n8:17:tasi\n9-22-g\n10:24:tasi\n11-29-h\n";
+        assert_eq!(expected, output(content, &["-n", "-b", "-C", "1"]));
+    }
+
+    #[test]
+    fn inverted() {
+        let content = "some text\nmore tasi there\nand tasi again\nlast";
+        assert_eq!("some text\nlast\n", output(content, &["-v"]));
+    }
+
+    #[test]
+    fn max_count() {
+        let content = "tasi 1\ntasi 2\nafter\ntasi 3";
+        assert_eq!("tasi 1\n", output(content, &["-m", "1"]));
+        // The context after the last match is still printed
+        assert_eq!(
+            "tasi 1\ntasi 2\nafter\n",
+            output(content, &["-m", "2", "-A", "1"])
+        );
+    }
+
+    #[test]
+    fn count_and_files() {
+        let content = "tasi 1\ntasi 2\nnothing";
+        assert_eq!("2\n", output(content, &["-c"]));
+        assert_eq!("1\n", output(content, &["-c", "-v"]));
+        assert_eq!("file.txt\n", output(content, &["-l"]));
+        assert_eq!("", output(content, &["-L"]));
+        assert_eq!("file.txt\n", output("nothing", &["-L"]));
+    }
+
+    #[test]
+    fn in_memory() {
+        let content = "tasi 1\r\nnothing\n\nmore tasi\ntasi\tasi\nend\ntasi";
+        for args in [
+            &["-n", "-b"][..],
+            &["-m", "2", "-n"],
+            &["-v"],
+            &["-C", "1"],
+            &["-c"],
+        ] {
+            assert_eq!(
+                search_with(content, args, false),
+                search_with(content, args, true),
+                "{args:?}"
+            );
+        }
+        assert_eq!(
+            "1:0:tasi 1\n4:17:more tasi\n5:27:tasi\tasi\n7:40:tasi\n",
+            search_with(content, &["-n", "-b"], true)
+        );
+        // Invalid UTF-8 is searched line by line
+        let content = "tasi \u{FFFD}\nnothing";
+        let mut bytes = content.as_bytes().to_vec();
+        bytes[5] = 0xFF;
+        let config = Config::build(["lilgrep", "-", "tasi"].map(String::from).into_iter());
+        let config = config.unwrap();
+        let mut printer = Printer::new(&config, false, Vec::new());
+        let matcher = Matcher::literal("tasi");
+        let count = search_slice(&bytes, &matcher, &mut printer, &config.search_options());
+        assert_eq!(1, count.unwrap());
+    }
+
+    #[test]
+    fn binary_input() {
+        let mut binary: &[u8] = b"tasi\0\x01";
+        assert!(is_binary(&mut binary).unwrap());
+        let mut text: &[u8] = b"tasi\n";
+        assert!(!is_binary(&mut text).unwrap());
+    }
+}
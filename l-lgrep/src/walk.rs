@@ -0,0 +1,341 @@
+use crate::{index, LilgrepError};
+use ignore::{
+    overrides::OverrideBuilder,
+    types::{Types, TypesBuilder},
+    WalkBuilder,
+};
+use std::{fs, path::PathBuf};
+
+// Which files of a directory are searched
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    // The include and exclude globs follow the .gitignore syntax, so
+    // "*.rs" matches any Rust file and "target/" matches only directories
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // --no-ignore, don't skip what .gitignore, .ignore and the global
+    // git excludes say to skip
+    pub no_ignore: bool,
+    // --hidden, also search hidden files and directories
+    pub hidden: bool,
+    // -t and -T, the names of the file types to search and to skip
+    pub types: Vec<String>,
+    pub types_not: Vec<String>,
+    // --type-add, more types or more globs for a type, as NAME:GLOB
+    pub type_add: Vec<String>,
+}
+
+// The file types every lilgrep knows, each one with its globs
+const DEFAULT_TYPES: &[(&str, &[&str])] = &[
+    ("html", &["*.htm", "*.html"]),
+    ("md", &["*.markdown", "*.md"]),
+    ("rust", &["*.rs"]),
+    ("toml", &["*.toml"]),
+    ("txt", &["*.txt"]),
+];
+
+// The default types plus the ones of --type-add. A definition that isn't
+// NAME:GLOB is an error
+fn type_definitions(type_add: &[String]) -> Result<TypesBuilder, ignore::Error> {
+    let mut types = TypesBuilder::new();
+    for (name, globs) in DEFAULT_TYPES {
+        for glob in *globs {
+            types.add(name, glob)?;
+        }
+    }
+    for definition in type_add {
+        types.add_def(definition)?;
+    }
+    Ok(types)
+}
+
+// Checks a --type-add definition on its own
+pub fn is_type_definition(definition: &str) -> bool {
+    TypesBuilder::new().add_def(definition).is_ok()
+}
+
+// If -t and -T know the type name, with the --type-add definitions
+pub fn is_type(name: &str, type_add: &[String]) -> bool {
+    type_definitions(type_add).is_ok_and(|types| {
+        types
+            .definitions()
+            .iter()
+            .any(|definition| definition.name() == name)
+    })
+}
+
+// The --type-list text, one type per line with its globs
+pub fn type_list(type_add: &[String]) -> String {
+    let Ok(types) = type_definitions(type_add) else {
+        return String::new();
+    };
+    types
+        .definitions()
+        .iter()
+        .map(|definition| format!("{}: {}\n", definition.name(), definition.globs().join(", ")))
+        .collect()
+}
+
+fn types(options: &WalkOptions) -> Result<Types, ignore::Error> {
+    let mut types = type_definitions(&options.type_add)?;
+    for name in &options.types {
+        types.select(name);
+    }
+    for name in &options.types_not {
+        types.negate(name);
+    }
+    types.build()
+}
+
+// Walks the root path and returns every file that should be searched.
+// If root is a single file, it's the only one returned
+pub fn files(root: &str, options: &WalkOptions) -> Result<Vec<PathBuf>, LilgrepError> {
+    // A missing root is an error of its own, not something to skip
+    fs::metadata(root).map_err(|err| LilgrepError::io(root, err))?;
+
+    // The globs given by the user. The walker only gets the excludes, as
+    // ! globs: a plain glob would be a whitelist, and whitelisted files
+    // skip the ignore files and the hidden check. The includes are
+    // checked on their own, on the files the walker gives back
+    let invalid = |glob: &str, source| LilgrepError::Glob {
+        glob: glob.to_string(),
+        source,
+    };
+    let mut excludes = OverrideBuilder::new(root);
+    for glob in &options.exclude {
+        excludes
+            .add(&format!("!{glob}"))
+            .map_err(|err| invalid(glob, err))?;
+    }
+    let excludes = excludes
+        .build()
+        .map_err(|err| invalid(&options.exclude.join(" "), err))?;
+    let mut includes = OverrideBuilder::new(root);
+    for glob in &options.include {
+        includes.add(glob).map_err(|err| invalid(glob, err))?;
+    }
+    let includes = includes
+        .build()
+        .map_err(|err| invalid(&options.include.join(" "), err))?;
+
+    // Only files of the -t types, and none of the -T ones. Like the
+    // includes they're checked after the walk, the types of -t are a
+    // whitelist too. They don't apply to a file given as the root either
+    let types = types(options).map_err(|err| invalid(&options.type_add.join(" "), err))?;
+
+    let ignore = !options.no_ignore;
+    let walker = WalkBuilder::new(root)
+        // Hidden files are the ones whose name starts with a dot
+        .hidden(!options.hidden)
+        // .ignore files, and .gitignore files plus the ignore files of
+        // the parent directories, the repository .git/info/exclude and
+        // the global core.excludesFile of git
+        .ignore(ignore)
+        .git_ignore(ignore)
+        .parents(ignore)
+        .git_exclude(ignore)
+        .git_global(ignore)
+        // .gitignore files count even outside a git repository
+        .require_git(false)
+        .overrides(excludes)
+        // Symbolic links are followed, the walker remembers the visited
+        // directories so a link pointing to a parent doesn't loop forever
+        .follow_links(true)
+        // Same input, same output order
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut files = Vec::new();
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                // The index of --indexed is never searched, even with
+                // --hidden
+                if !entry.file_type().is_some_and(|kind| kind.is_file())
+                    || entry.file_name() == index::INDEX_FILE
+                {
+                    continue;
+                }
+                // A file given as the root is searched whatever the
+                // includes and the types say
+                if entry.depth() > 0
+                    && (includes.matched(entry.path(), false).is_ignore()
+                        || types.matched(entry.path(), false).is_ignore())
+                {
+                    continue;
+                }
+                files.push(entry.into_path());
+            }
+            // A symlink loop or a directory that can't be read must not
+            // stop the whole search, it's just skipped
+            Err(err) => eprintln!("lilgrep: {err}"),
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn walks_whole_tree() {
+        let files = files("resources/tree", &WalkOptions::default()).unwrap();
+        assert_eq!(
+            vec![
+                "resources/tree/build/generated.rs",
+                "resources/tree/data.bin",
+                "resources/tree/notes.txt",
+                "resources/tree/src/lib.rs",
+                "resources/tree/src/main.rs",
+            ],
+            names(files)
+        );
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let options = WalkOptions {
+            include: vec!["*.rs".to_string()],
+            exclude: vec!["build/".to_string()],
+            ..Default::default()
+        };
+        let files = files("resources/tree", &options).unwrap();
+        assert_eq!(
+            vec!["resources/tree/src/lib.rs", "resources/tree/src/main.rs"],
+            names(files)
+        );
+    }
+
+    #[test]
+    fn include_keeps_ignored_and_hidden_out() {
+        for glob in ["*.txt", "*.log"] {
+            let options = WalkOptions {
+                include: vec![glob.to_string()],
+                ..Default::default()
+            };
+            let files = names(files("resources/ignored", &options).unwrap());
+            // secret.txt is in .ignore, app.log in .gitignore and
+            // .hidden.txt is hidden
+            let expected: Vec<&str> = match glob {
+                "*.txt" => vec!["resources/ignored/notes.txt"],
+                _ => vec![],
+            };
+            assert_eq!(expected, files);
+        }
+        let options = WalkOptions {
+            include: vec!["*.txt".to_string()],
+            hidden: true,
+            no_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "resources/ignored/.hidden.txt",
+                "resources/ignored/notes.txt",
+                "resources/ignored/secret.txt",
+            ],
+            names(files("resources/ignored", &options).unwrap())
+        );
+        // The root itself is never filtered
+        let options = WalkOptions {
+            include: vec!["*.rs".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["resources/file.txt"],
+            names(files("resources/file.txt", &options).unwrap())
+        );
+    }
+
+    #[test]
+    fn file_types() {
+        let options = WalkOptions {
+            types: vec!["rust".to_string()],
+            types_not: vec!["generated".to_string()],
+            type_add: vec!["generated:generated.*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["resources/tree/src/lib.rs", "resources/tree/src/main.rs"],
+            names(files("resources/tree", &options).unwrap())
+        );
+        // A type doesn't bring back hidden or ignored files
+        let options = WalkOptions {
+            types: vec!["txt".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["resources/ignored/notes.txt"],
+            names(files("resources/ignored", &options).unwrap())
+        );
+
+        assert!(is_type("md", &[]));
+        assert!(!is_type("generated", &[]));
+        assert!(!is_type_definition("no glob"));
+        let list = type_list(&["rust:*.rs.in".to_string()]);
+        assert!(list.contains("rust: *.rs, *.rs.in\n"));
+        assert!(list.starts_with("html: *.htm, *.html\n"));
+    }
+
+    #[test]
+    fn single_file() {
+        let files = files("resources/file.txt", &WalkOptions::default()).unwrap();
+        assert_eq!(vec!["resources/file.txt"], names(files));
+    }
+
+    #[test]
+    fn honors_ignore_files() {
+        let files = files("resources/ignored", &WalkOptions::default()).unwrap();
+        assert_eq!(
+            vec![
+                "resources/ignored/notes.txt",
+                "resources/ignored/src/main.rs"
+            ],
+            names(files)
+        );
+    }
+
+    #[test]
+    fn hidden_and_no_ignore() {
+        let options = WalkOptions {
+            hidden: true,
+            ..Default::default()
+        };
+        let hidden = files("resources/ignored", &options).unwrap();
+        assert!(names(hidden).contains(&"resources/ignored/.hidden.txt".to_string()));
+
+        let options = WalkOptions {
+            no_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "resources/ignored/app.log",
+                "resources/ignored/build/generated.rs",
+                "resources/ignored/notes.txt",
+                "resources/ignored/secret.txt",
+                "resources/ignored/src/main.rs",
+            ],
+            names(files("resources/ignored", &options).unwrap())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt"), "tasi").unwrap();
+        // A link pointing to its own parent directory
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+        let files = files(dir.path().to_str().unwrap(), &WalkOptions::default()).unwrap();
+        assert_eq!(1, files.len());
+    }
+}